@@ -1,28 +1,54 @@
-use crate::Arg;
+use crate::core::ArgDef;
 
-pub(crate) struct ArgsContext<'a> {
-    args: &'a [Arg],
+pub(crate) struct ArgsContext<'a, A> {
+    args: &'a [A],
     cur_arg_values_count: usize,
     start_idx: usize,
+    double_dashed: bool,
 }
-impl<'a> ArgsContext<'a> {
-    pub fn new(args: &'a [Arg]) -> Self {
+// Derived `Clone` would require `A: Clone`, which is not needed for a slice.
+impl<A> Clone for ArgsContext<'_, A> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+impl<'a, A: ArgDef> ArgsContext<'a, A> {
+    pub fn new(args: &'a [A]) -> Self {
         Self {
             args,
             start_idx: 0,
             cur_arg_values_count: 0,
+            double_dashed: false,
+        }
+    }
+    /// The index of the value that `next_arg` will give, within the same arg.
+    pub fn value_index(&self) -> usize {
+        self.cur_arg_values_count
+    }
+    pub fn is_double_dashed(&self) -> bool {
+        self.double_dashed
+    }
+    /// Called when seeing `--`. If there is an arg marked as `last`,
+    /// everything after `--` goes to it, just like what clap does.
+    pub fn double_dash(&mut self) {
+        self.double_dashed = true;
+        if let Some(idx) = self.args.iter().position(|a| a.last()) {
+            self.start_idx = idx;
+            self.cur_arg_values_count = 0;
         }
     }
     pub fn has_seen_arg(&self) -> bool {
         self.start_idx != 0 || self.cur_arg_values_count != 0
     }
-    pub fn next_arg(&mut self) -> Option<&Arg> {
+    pub fn next_arg(&mut self) -> Option<&A> {
         log::debug!("next arg called");
         let args = &self.args[self.start_idx..];
-        let Some(next) = args.iter().next() else {
+        let next = args.iter().next()?;
+        if next.last() && !self.double_dashed {
+            log::info!("arg {:?} is only available after `--`", next.id());
             return None;
-        };
-        if next.max_values == self.cur_arg_values_count + 1 {
+        }
+        if next.max_values() == self.cur_arg_values_count + 1 {
             self.start_idx += 1;
             self.cur_arg_values_count = 0;
         } else {
@@ -36,51 +62,63 @@ impl<'a> ArgsContext<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::id;
+    use crate::{Arg, id};
 
     const ARG1: Arg = Arg {
         id: id::Valued::Single(id::SingleVal::new(line!(), "")),
-        comp_options: |_, _| vec![],
+        comp_options: |_, _, _, _| Ok(vec![]),
         max_values: 1,
+        last: false,
+        value_delimiter: None,
+        required: false,
     };
     const ARG2: Arg = Arg {
         id: id::Valued::Single(id::SingleVal::new(line!(), "")),
-        comp_options: |_, _| vec![],
+        comp_options: |_, _, _, _| Ok(vec![]),
         max_values: 1,
+        last: false,
+        value_delimiter: None,
+        required: false,
     };
     #[test]
     fn test_empty_arg_ctx() {
-        let mut ctx = ArgsContext::new(&[]);
-        assert_eq!(ctx.has_seen_arg(), false);
+        let mut ctx = ArgsContext::<Arg>::new(&[]);
+        assert!(!ctx.has_seen_arg());
         assert!(ctx.next_arg().is_none());
-        assert_eq!(ctx.has_seen_arg(), false);
+        assert!(!ctx.has_seen_arg());
     }
     #[test]
     fn test_simple_arg_ctx() {
         let mut ctx = ArgsContext::new(&[ARG1, ARG2]);
-        assert_eq!(ctx.has_seen_arg(), false);
+        assert!(!ctx.has_seen_arg());
         assert_eq!(ctx.next_arg().unwrap().id, ARG1.id);
-        assert_eq!(ctx.has_seen_arg(), true);
+        assert!(ctx.has_seen_arg());
         assert_eq!(ctx.next_arg().unwrap().id, ARG2.id);
         assert!(ctx.next_arg().is_none());
     }
 
     const ARG3: Arg = Arg {
         id: id::Valued::Single(id::SingleVal::new(line!(), "")),
-        comp_options: |_, _| vec![],
+        comp_options: |_, _, _, _| Ok(vec![]),
         max_values: 2,
+        last: false,
+        value_delimiter: None,
+        required: false,
     };
     const ARG4: Arg = Arg {
         id: id::Valued::Single(id::SingleVal::new(line!(), "")),
-        comp_options: |_, _| vec![],
+        comp_options: |_, _, _, _| Ok(vec![]),
         max_values: 3,
+        last: false,
+        value_delimiter: None,
+        required: false,
     };
     #[test]
     fn test_var_arg_ctx() {
         let mut ctx = ArgsContext::new(&[ARG1, ARG3, ARG4]);
-        assert_eq!(ctx.has_seen_arg(), false);
+        assert!(!ctx.has_seen_arg());
         assert_eq!(ctx.next_arg().unwrap().id, ARG1.id);
-        assert_eq!(ctx.has_seen_arg(), true);
+        assert!(ctx.has_seen_arg());
 
         assert_eq!(ctx.next_arg().unwrap().id, ARG3.id);
         assert_eq!(ctx.next_arg().unwrap().id, ARG3.id);
@@ -91,4 +129,35 @@ mod test {
 
         assert!(ctx.next_arg().is_none());
     }
+
+    const LAST_ARG: Arg = Arg {
+        id: id::Valued::Multi(id::MultiVal::new(line!(), "")),
+        comp_options: |_, _, _, _| Ok(vec![]),
+        max_values: usize::MAX,
+        last: true,
+        value_delimiter: None,
+        required: false,
+    };
+    #[test]
+    fn test_last_arg_ctx() {
+        let mut ctx = ArgsContext::new(&[ARG1, LAST_ARG]);
+        assert_eq!(ctx.next_arg().unwrap().id, ARG1.id);
+        assert!(ctx.next_arg().is_none());
+
+        let mut ctx = ArgsContext::new(&[ARG1, ARG3, LAST_ARG]);
+        assert!(!ctx.is_double_dashed());
+        ctx.double_dash();
+        assert!(ctx.is_double_dashed());
+        assert_eq!(ctx.next_arg().unwrap().id, LAST_ARG.id);
+        assert_eq!(ctx.next_arg().unwrap().id, LAST_ARG.id);
+    }
+    #[test]
+    fn test_double_dash_arg_ctx() {
+        let mut ctx = ArgsContext::new(&[ARG1, ARG2]);
+        ctx.double_dash();
+        assert!(!ctx.has_seen_arg());
+        assert_eq!(ctx.next_arg().unwrap().id, ARG1.id);
+        assert_eq!(ctx.next_arg().unwrap().id, ARG2.id);
+        assert!(ctx.next_arg().is_none());
+    }
 }
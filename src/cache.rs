@@ -0,0 +1,412 @@
+//! On-disk cache for `comp_options` that are slow to compute, e.g. the ones shelling out to `git branch`.
+//!
+//! Completers opt in by wrapping their work with `Cache::get_or_update`:
+//! ```no_run
+//! use std::time::Duration;
+//! use supplements::cache::Cache;
+//! use supplements::{CompResult, Completion, History, id};
+//! # const ID_ARG_BRANCH: id::SingleVal = id::SingleVal::new(0, "branch");
+//! # const ID_FLAG_GIT_DIR: id::SingleVal = id::SingleVal::new(1, "git-dir");
+//!
+//! fn comp_options(cache: &Cache, history: &History, _arg: &str, _index: usize) -> CompResult {
+//!     // Results differ by `--git-dir`, so it's part of the key
+//!     let deps = [ID_FLAG_GIT_DIR.into()];
+//!     cache.get_or_update(ID_ARG_BRANCH, &deps, history, || {
+//!         Ok(vec![Completion::new("main", "")])
+//!     })
+//! }
+//! let cache = Cache::new("my-git")
+//!     .ttl(Duration::from_secs(10))
+//!     .stale(Duration::from_secs(600));
+//! ```
+//! Entries are keyed by the id of the flag or arg, the values of `deps` in `History` and the current directory.
+//!
+//! A stale entry is served right away and refreshed afterwards, see `Cache::stale` and `refresh_stale`.
+
+use crate::history::HistoryUnit;
+use crate::{CompResult, Completion, History, id};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+type Refresh = Box<dyn FnOnce() + Send>;
+
+/// Refreshes of the stale entries served so far, run by `refresh_stale`.
+static PENDING: Mutex<Vec<Refresh>> = Mutex::new(Vec::new());
+
+/// Recompute and store the stale entries served so far, so the next completion sees new values.
+/// `CompleteEnv::complete` calls it after the completions are printed and stdout is closed,
+/// so only call it yourself when completing without `CompleteEnv`, after the output is written.
+pub fn refresh_stale() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+    for refresh in pending {
+        refresh();
+    }
+}
+
+pub(crate) fn has_stale() -> bool {
+    !PENDING.lock().unwrap().is_empty()
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+    stale: Duration,
+}
+
+enum Lookup {
+    Fresh(Vec<Completion>),
+    Stale(Vec<Completion>),
+    Miss,
+}
+
+impl Cache {
+    /// Store the entries under `$XDG_CACHE_HOME/<app>`, or `~/.cache/<app>` if it's not set.
+    pub fn new(app: &str) -> Self {
+        let base = match (std::env::var_os("XDG_CACHE_HOME"), std::env::home_dir()) {
+            (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+            (_, Some(home)) => home.join(".cache"),
+            _ => std::env::temp_dir(),
+        };
+        Self::with_dir(base.join(app))
+    }
+    /// Store the entries under `dir`.
+    pub fn with_dir(dir: impl Into<PathBuf>) -> Self {
+        Cache {
+            dir: dir.into(),
+            ttl: Duration::from_secs(60),
+            stale: Duration::ZERO,
+        }
+    }
+    /// How long an entry stays fresh. Default to 1 minute.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    /// How long an entry can still be used after it's no longer fresh. Default to zero.
+    /// Within this window, the stale entry is returned right away, and recomputed by `refresh_stale`
+    /// once the completions are printed. Past this window, it's recomputed before returning.
+    pub fn stale(mut self, stale: Duration) -> Self {
+        self.stale = stale;
+        self
+    }
+
+    /// Return the cached completions, or compute them with `f` and store them.
+    /// Errors of `f` are returned as they are and never stored.
+    /// Failing to read or write the cache only gets logged, and `f` is used as if there is no cache.
+    ///
+    /// `f` may outlive the call to refresh a stale entry, so it owns what it needs, e.g. a clone of the context.
+    pub fn get_or_update(
+        &self,
+        id: impl Into<id::Any>,
+        deps: &[id::Any],
+        history: &History,
+        f: impl FnOnce() -> CompResult + Send + 'static,
+    ) -> CompResult {
+        let id = id.into();
+        let key = match key(id, deps, history) {
+            Ok(key) => key,
+            Err(err) => {
+                log::warn!("failed to create the cache key of `{}`: {err}", id.name());
+                return f();
+            }
+        };
+        let path = self.entry_path(id, &key);
+        match self.lookup(&path, &key) {
+            Lookup::Fresh(comps) => {
+                log::debug!("cache hit for `{}`", id.name());
+                Ok(comps)
+            }
+            Lookup::Stale(comps) => {
+                log::info!("cache for `{}` is stale, refresh it later", id.name());
+                let refresh = move || match f() {
+                    Ok(comps) => store(&path, &key, &comps),
+                    Err(err) => log::warn!("failed to refresh cache of `{}`: {err:?}", id.name()),
+                };
+                PENDING.lock().unwrap().push(Box::new(refresh));
+                Ok(comps)
+            }
+            Lookup::Miss => {
+                let comps = f()?;
+                store(&path, &key, &comps);
+                Ok(comps)
+            }
+        }
+    }
+
+    /// Remove all the entries of a flag or arg, e.g. after a command that changes the branches.
+    pub fn invalidate(&self, id: impl Into<id::Any>) -> IoResult<()> {
+        remove_dir(&self.dir.join(completer_dir(id.into())))
+    }
+    /// Remove all the entries.
+    pub fn clear(&self) -> IoResult<()> {
+        remove_dir(&self.dir)
+    }
+
+    fn entry_path(&self, id: id::Any, key: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let file = format!("{:016x}", hasher.finish());
+        self.dir.join(completer_dir(id)).join(file)
+    }
+
+    fn lookup(&self, path: &Path, key: &str) -> Lookup {
+        let age = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(|t| SystemTime::now().duration_since(t).unwrap_or_default());
+        let Ok(age) = age else {
+            return Lookup::Miss;
+        };
+        if age > self.ttl + self.stale {
+            log::debug!("cache at {path:?} is expired");
+            return Lookup::Miss;
+        }
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                log::warn!("failed to read cache at {path:?}: {err}");
+                return Lookup::Miss;
+            }
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(escape(key).as_str()) {
+            log::info!("cache at {path:?} has another key");
+            return Lookup::Miss;
+        }
+        let comps = lines.map(parse_completion).collect();
+        if age > self.ttl {
+            Lookup::Stale(comps)
+        } else {
+            Lookup::Fresh(comps)
+        }
+    }
+}
+
+/// Entries of the same flag or arg are in the same directory, so they can be invalidated together.
+/// The number in the id tells apart flags and args with the same name in different subcommands.
+fn completer_dir(id: id::Any) -> String {
+    let name = id.name().replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    if name.is_empty() {
+        return id.num().to_string();
+    }
+    format!("{name}-{}", id.num())
+}
+
+fn key(id: id::Any, deps: &[id::Any], history: &History) -> IoResult<String> {
+    let cwd = std::env::current_dir()?;
+    let mut key = format!("{}-{}\0{}", id.name(), id.num(), cwd.to_string_lossy());
+    for dep in deps {
+        key.push('\0');
+        match history.find(*dep) {
+            None => (),
+            Some(HistoryUnit::No(h)) => key += &h.count.to_string(),
+            Some(HistoryUnit::Single(h)) => key += &h.value,
+            Some(HistoryUnit::Multi(h)) => key += &h.values.join("\0"),
+        }
+    }
+    Ok(key)
+}
+
+/// Write to a temporary file first, so readers never see a half-written entry.
+fn store(path: &Path, key: &str, comps: &[Completion]) {
+    let mut content = escape(key);
+    for comp in comps {
+        content += &format!(
+            "\n{}\t{}\t{}\t{}\t{}\t{}",
+            escape(&comp.value),
+            escape(&comp.description),
+            escape(comp.group.unwrap_or_default()),
+            escape(comp.display.as_deref().unwrap_or_default()),
+            if comp.no_space { "nospace" } else { "" },
+            comp.typed_prefix,
+        );
+    }
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    let res = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&tmp, content))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(err) = res {
+        log::warn!("failed to write cache at {path:?}: {err}");
+    }
+}
+
+fn remove_dir(dir: &Path) -> IoResult<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+fn parse_completion(line: &str) -> Completion {
+    let mut fields = line.split('\t').map(unescape);
+    let value = fields.next().unwrap_or_default();
+    let description = fields.next().unwrap_or_default();
+    let mut comp = Completion::new(&value, &description);
+    comp.group = fields.next().filter(|g| !g.is_empty()).map(intern);
+    comp.display = fields.next().filter(|d| !d.is_empty());
+    comp.no_space = fields.next().is_some_and(|n| n == "nospace");
+    comp.typed_prefix = fields.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    comp
+}
+
+/// Groups are `&'static str`. Only a handful of them exist, so each distinct one is leaked once
+/// and reused, instead of on every read.
+fn intern(group: String) -> &'static str {
+    static GROUPS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut groups = GROUPS.lock().unwrap();
+    if let Some(g) = groups.iter().find(|g| **g == group) {
+        return g;
+    }
+    let g: &'static str = Box::leak(group.into_boxed_str());
+    groups.push(g);
+    g
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\0', "\\0")
+}
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            ret.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => ret.push('\t'),
+            Some('n') => ret.push('\n'),
+            Some('0') => ret.push('\0'),
+            Some(ch) => ret.push(ch),
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const ID: id::SingleVal = id::SingleVal::new(1, "branch");
+    const DEP: id::SingleVal = id::SingleVal::new(2, "git-dir");
+
+    fn temp_cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("supplements-{name}-{}", std::process::id()));
+        let cache = Cache::with_dir(dir);
+        cache.clear().unwrap();
+        cache
+    }
+
+    /// Returns the completer, and how many times it's called.
+    fn counter(value: &'static str) -> (Arc<AtomicUsize>, impl Fn() -> CompResult + Clone) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_clone = count.clone();
+        let f = move || {
+            count_clone.fetch_add(1, Ordering::SeqCst);
+            let comp = Completion::new(value, "a\tb").group("Branch");
+            Ok(vec![comp.display("*").no_space().typed_prefix(1)])
+        };
+        (count, f)
+    }
+
+    #[test]
+    fn test_hit_and_invalidate() {
+        let cache = temp_cache("hit");
+        let history = History::default();
+        let (count, f) = counter("main\nx");
+
+        let expected = Completion::new("main\nx", "a\tb").group("Branch");
+        let expected = vec![expected.display("*").no_space().typed_prefix(1)];
+        assert_eq!(
+            cache.get_or_update(ID, &[], &history, f.clone()),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            cache.get_or_update(ID, &[], &history, f.clone()),
+            Ok(expected)
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        cache.invalidate(ID).unwrap();
+        cache.get_or_update(ID, &[], &history, f.clone()).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        // Errors are not stored
+        cache.invalidate(ID).unwrap();
+        let err = cache.get_or_update(ID, &[], &history, || Err("boom".into()));
+        assert!(err.is_err());
+        cache.get_or_update(ID, &[], &history, f).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn test_intern() {
+        let group = intern("Branch".to_owned());
+        assert_eq!(group, "Branch");
+        assert!(std::ptr::eq(group, intern("Branch".to_owned())));
+    }
+
+    #[test]
+    fn test_deps() {
+        let cache = temp_cache("deps");
+        let (count, f) = counter("main");
+        let history = History::from_vec(vec![HistoryUnit::Single(
+            crate::history::HistoryUnitSingleVal {
+                id: DEP,
+                value: "dir1".to_owned(),
+            },
+        )]);
+        let deps = [DEP.into()];
+        cache.get_or_update(ID, &deps, &history, f.clone()).unwrap();
+        cache.get_or_update(ID, &deps, &history, f.clone()).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        cache
+            .get_or_update(ID, &deps, &History::default(), f)
+            .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn test_ttl() {
+        let history = History::default();
+        let cache = temp_cache("ttl").ttl(Duration::ZERO);
+        let (count, f) = counter("main");
+        cache.get_or_update(ID, &[], &history, f.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        cache.get_or_update(ID, &[], &history, f).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        // The stale entry is returned, and refreshed afterwards
+        let cache = cache.stale(Duration::from_secs(60));
+        let (count, f) = counter("dev");
+        let comps = cache.get_or_update(ID, &[], &history, f.clone()).unwrap();
+        assert_eq!(comps[0].value, "main");
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        refresh_stale();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let comps = cache.get_or_update(ID, &[], &history, f.clone()).unwrap();
+        assert_eq!(comps[0].value, "dev");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        refresh_stale();
+
+        let (count, f) = counter("main");
+        cache.invalidate(ID).unwrap();
+        let comps = cache.get_or_update(ID, &[], &history, f).unwrap();
+        assert_eq!(comps[0].value, "main");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        cache.clear().unwrap();
+    }
+}
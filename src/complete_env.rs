@@ -0,0 +1,243 @@
+use crate::cache;
+use crate::completion::{CompletionGroup, print_json, print_message};
+use crate::core::CommandDef;
+use crate::error::Error;
+use crate::guard::{Runner, SpawnFn, spawn};
+use crate::line::Line;
+use crate::{Command, Guard, History, Invocation, Shell};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+
+/// The one-call entry point of completion. Put it at the start of `main`, before any parsing:
+/// ```no_run
+/// # use supplements::{Command, CompleteEnv};
+/// # fn cmd() -> &'static Command { unimplemented!() }
+/// fn main() {
+///     CompleteEnv::new(cmd()).complete();
+///     // not completing, parse the args as usual...
+/// }
+/// ```
+/// It's triggered by the environment variable, e.g. `COMPLETE=fish my-bin --line <line> <cursor>`,
+/// or by a hidden subcommand if set, e.g. `my-bin complete fish --line <line> <cursor>`.
+/// Once triggered, it prints the completions of the shell and exits.
+///
+/// Without any args after the trigger, e.g. `COMPLETE=fish my-bin`, it prints the script to register
+/// the completion instead, see `Shell::print_script`.
+///
+/// Tools other than shells, e.g. editors, can trigger it with `json` in place of the shell,
+/// e.g. `COMPLETE=json my-bin my-bin log --gr`, to get the result by `completion::print_json`.
+///
+/// For commands with a context, create it with `CompleteEnv::with_ctx`.
+pub struct CompleteEnv<'a, Ctx: 'static = ()> {
+    cmd: &'a Command<Ctx>,
+    ctx: &'a Ctx,
+    var: &'a str,
+    subcommand: Option<&'a str>,
+    names: Option<&'a [&'a str]>,
+    bin: Option<&'a str>,
+    guard: Option<(&'a Guard, SpawnFn<Ctx>)>,
+}
+
+impl<'a> CompleteEnv<'a> {
+    pub fn new(cmd: &'a Command) -> Self {
+        Self::with_ctx(cmd, &())
+    }
+}
+
+impl<'a, Ctx> CompleteEnv<'a, Ctx> {
+    /// `ctx` is passed to every `comp_options`, see `Command::supplement_with_ctx`.
+    pub fn with_ctx(cmd: &'a Command<Ctx>, ctx: &'a Ctx) -> Self {
+        CompleteEnv {
+            cmd,
+            ctx,
+            var: "COMPLETE",
+            subcommand: None,
+            names: None,
+            bin: None,
+            guard: None,
+        }
+    }
+    /// The environment variable to trigger completion. Default to `COMPLETE`.
+    pub fn var(mut self, var: &'a str) -> Self {
+        self.var = var;
+        self
+    }
+    /// Also trigger completion by a subcommand, in case setting environment variable is not an option.
+    /// Make sure it doesn't clash with the real subcommands. If set, the script calls the binary with it.
+    pub fn subcommand(mut self, subcommand: &'a str) -> Self {
+        self.subcommand = Some(subcommand);
+        self
+    }
+    /// The command names to register in the script, e.g. `&["git", "g"]`.
+    /// Default to the file name of the binary.
+    pub fn names(mut self, names: &'a [&'a str]) -> Self {
+        self.names = Some(names);
+        self
+    }
+    /// How the script calls the binary. Default to the path of the current executable.
+    pub fn bin(mut self, bin: &'a str) -> Self {
+        self.bin = Some(bin);
+        self
+    }
+
+    /// Complete and exit if triggered, otherwise just return.
+    ///
+    /// The environment variable is removed, so that it's not passed down to the processes
+    /// spawned by `comp_options`, or by the command itself when not completing.
+    ///
+    /// Stale cache entries served are refreshed before exiting, see `cache::refresh_stale`.
+    pub fn complete(self) {
+        let env = std::env::var(self.var).ok();
+        if env.is_some() {
+            // SAFETY: This is meant to be called at the start of `main`, before any other thread is spawned.
+            unsafe { std::env::remove_var(self.var) };
+        }
+        let mut stdout = std::io::stdout();
+        match self.try_complete(std::env::args(), env, &mut stdout) {
+            Ok(false) => (),
+            Ok(true) => {
+                let _ = stdout.flush();
+                if cache::has_stale() {
+                    close_stdout();
+                    cache::refresh_stale();
+                }
+                std::process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Returns `true` if completion is triggered.
+    /// `env` is the value of the environment variable, if any. Like `clap_complete`,
+    /// an empty value or `0` doesn't trigger completion.
+    pub fn try_complete(
+        &self,
+        args: impl Iterator<Item = String>,
+        env: Option<String>,
+        w: &mut impl Write,
+    ) -> IoResult<bool> {
+        let args: Vec<_> = args.collect();
+        let env = env.filter(|v| !v.is_empty() && v != "0");
+        let (shell, rest) = match (env, self.subcommand) {
+            (Some(shell), _) => (shell, args.get(1..).unwrap_or_default()),
+            (None, Some(sub)) if args.get(1).map(|s| s.as_str()) == Some(sub) => {
+                match args.get(2) {
+                    Some(shell) => (shell.clone(), &args[3..]),
+                    None => return Err(IoError::new(ErrorKind::InvalidInput, "no shell is given")),
+                }
+            }
+            _ => return Ok(false),
+        };
+        let shell: Option<Shell> = match shell.as_str() {
+            "json" => None,
+            shell => Some(
+                shell
+                    .parse()
+                    .map_err(|msg| IoError::new(ErrorKind::InvalidInput, msg))?,
+            ),
+        };
+        log::info!("complete for {:?} with {:?}", shell, rest);
+
+        let runner = match self.guard {
+            Some((guard, spawn)) => Runner::guarded(self.ctx, guard, spawn),
+            None => Runner::new(self.ctx),
+        };
+        let (res, arg) = match rest {
+            [] => {
+                let Some(shell) = shell else {
+                    let msg = "there's no script for json";
+                    return Err(IoError::new(ErrorKind::InvalidInput, msg));
+                };
+                self.print_script(shell, args.first(), w)?;
+                return Ok(true);
+            }
+            [flag, line, cursor] if flag == "--line" => {
+                let cursor: usize = cursor
+                    .parse()
+                    .map_err(|_| IoError::new(ErrorKind::InvalidInput, "invalid cursor"))?;
+                // Shells count the cursor in characters
+                let cursor = line
+                    .char_indices()
+                    .nth(cursor)
+                    .map_or(line.len(), |(i, _)| i);
+                let mut history = History::default();
+                let res = self
+                    .cmd
+                    .supplement_line_with_runner(&runner, &mut history, line, cursor);
+                (res, Line::new(line, cursor).typed().to_owned())
+            }
+            words => {
+                let mut history = History::default();
+                let arg = words.last().cloned().unwrap_or_default();
+                let words = words.iter().cloned();
+                let res = self
+                    .cmd
+                    .supplement_with_trailing(&runner, &mut history, words, &[]);
+                (res, arg)
+            }
+        };
+        let Some(shell) = shell else {
+            print_json(&res, w)?;
+            return Ok(true);
+        };
+        let grp = match res {
+            Ok(grp) => grp,
+            Err(Error::Completer(msg)) => {
+                log::warn!("completer error: {msg}");
+                print_message(shell, &arg, &msg, w)?;
+                return Ok(true);
+            }
+            Err(err) => {
+                log::warn!("completion error: {err}");
+                CompletionGroup::new(vec![], String::new())
+            }
+        };
+        grp.print(shell, w)?;
+        Ok(true)
+    }
+
+    fn print_script(
+        &self,
+        shell: Shell,
+        arg0: Option<&String>,
+        w: &mut impl Write,
+    ) -> IoResult<()> {
+        let exe = std::env::current_exe()?;
+        let exe = exe.to_string_lossy();
+        let bin = self.bin.unwrap_or(&exe);
+        let name = arg0
+            .and_then(|s| std::path::Path::new(s).file_name())
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let names = self.names.map_or(vec![name.as_str()], |n| n.to_vec());
+
+        match self.subcommand {
+            Some(sub) => shell.print_script(&names, format!("{bin} {sub}").as_str(), w),
+            None => shell.print_script(&names, Invocation::Env { var: self.var, bin }, w),
+        }
+    }
+}
+
+/// The shell reads the completions until stdout is closed, so close it before any slow work.
+/// `/dev/null` takes its place, since it's the lowest free fd, so nothing else ends up at fd 1.
+#[cfg(unix)]
+fn close_stdout() {
+    use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
+    // SAFETY: Nothing else owns fd 1, and stdout is flushed and never written to again.
+    drop(unsafe { OwnedFd::from_raw_fd(1) });
+    if let Ok(null) = std::fs::File::options().write(true).open("/dev/null") {
+        let _ = null.into_raw_fd();
+    }
+}
+#[cfg(not(unix))]
+fn close_stdout() {}
+
+impl<'a, Ctx: Clone + Send> CompleteEnv<'a, Ctx> {
+    /// Run every `comp_options` under the guard, see `Command::supplement_with_guard`.
+    pub fn guard(mut self, guard: &'a Guard) -> Self {
+        self.guard = Some((guard, spawn));
+        self
+    }
+}
@@ -1,3 +1,4 @@
+use crate::line::Quote;
 use std::fs;
 use std::io::Result as IoResult;
 use std::io::Write;
@@ -16,32 +17,112 @@ use std::path::{MAIN_SEPARATOR_STR, Path};
 /// ```
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Completion {
+    /// What gets inserted.
     pub value: String,
+    /// What gets shown in place of the value, if the shell supports it. See `Completion::display`.
+    pub display: Option<String>,
     pub description: String,
     pub group: Option<&'static str>,
+    /// Don't add a space after inserting it, because the user is expected to type more,
+    /// e.g. directories like `src/`, flags like `--color=`, or prefixes like `user@host:`.
+    pub no_space: bool,
+    /// How many bytes at the start of the value are printed without escaping, see `Completion::typed_prefix`.
+    pub typed_prefix: usize,
 }
 impl Completion {
     pub fn new(value: &str, description: &str) -> Self {
         Completion {
             value: value.to_owned(),
+            display: None,
             description: description.to_owned(),
             group: None,
+            no_space: false,
+            typed_prefix: 0,
         }
     }
     pub fn value<F: FnOnce(&str) -> String>(mut self, val: F) -> Self {
         self.value = val(&self.value);
         self
     }
+    /// Show something else than the inserted value, e.g. show `main  (current branch)` while inserting `main`,
+    /// or show `file.rs` while inserting `src/file.rs`.
+    /// Zsh, powershell and elvish show it in the list. Other shells always show the value.
+    pub fn display(mut self, display: &str) -> Self {
+        self.display = Some(display.to_owned());
+        self
+    }
     pub fn group(mut self, group: &'static str) -> Self {
         self.group = Some(group);
         self
     }
+    /// See the `no_space` field. Each shell has its own way to do it:
+    /// - bash: `compopt -o nospace`. It applies to the whole list, so it's set if any of them has it.
+    /// - zsh: `compadd -S ''`.
+    /// - elvish: an empty `code-suffix`.
+    /// - fish decides by itself, and never adds a space after values ending with one of `/=@:.,-`.
+    /// - powershell and nushell always add a space.
+    pub fn no_space(mut self) -> Self {
+        self.no_space = true;
+        self
+    }
+    /// Keep the first `len` bytes of the value as the user typed them, instead of escaping them,
+    /// e.g. `$HOME/` in `$HOME/src/`, so that the shell still expands it.
+    pub fn typed_prefix(mut self, len: usize) -> Self {
+        self.typed_prefix = len;
+        self
+    }
+    /// Like `value`, but the original value is still shown, unless there's already a display text.
+    /// e.g. `--color=<TAB>` shows `always` and `never`, while inserting `--color=always` and `--color=never`.
+    ///
+    /// The new value always starts with what's typed before the original one, e.g. `--color=`,
+    /// so a typed prefix is extended to cover it.
+    pub(crate) fn rewrite<F: FnOnce(&str) -> String>(mut self, val: F) -> Self {
+        if self.display.is_none() {
+            self.display = Some(self.value.clone());
+        }
+        let len = self.value.len();
+        let mut comp = self.value(val);
+        if comp.typed_prefix > 0 {
+            comp.typed_prefix += comp.value.len() - len;
+        }
+        comp
+    }
+    /// Split the value into the typed prefix, and the rest to be escaped.
+    fn split_typed(&self) -> (&str, &str) {
+        let len = self.typed_prefix.min(self.value.len());
+        match self.value.is_char_boundary(len) {
+            true => self.value.split_at(len),
+            false => ("", &self.value),
+        }
+    }
+    fn display_text(&self) -> &str {
+        self.display.as_deref().unwrap_or(&self.value)
+    }
+    /// What the completion is, based on its value and group.
+    fn kind(&self) -> &'static str {
+        if self.value.starts_with('-') {
+            "flag"
+        } else if self.group == Some("command") {
+            "command"
+        } else {
+            "value"
+        }
+    }
+    /// For shells without groups, show the group in place of an empty description.
+    fn description_or_group(&self) -> &str {
+        match (self.description.as_str(), self.group) {
+            ("", Some(g)) => g,
+            (desc, _) => desc,
+        }
+    }
     /// Generate completion by file. e.g.
     /// - `ls <TAB>` - everything under current directory
     /// - `ls xyz<TAB>` - everything under current directory
     /// - `ls xyz/<TAB>` - everything under `xyz` directory
     /// - `ls another/xyz<TAB>` - everything under `another` directory
     /// - `ls /xyz<TAB>` - everything under `/` directory
+    ///
+    /// See `providers::Files` for filters, hidden files and `~/` expansion.
     pub fn files(arg: &str) -> impl Iterator<Item = Completion> {
         let path = Path::new(arg);
         let (arg_dir, dir) = match arg {
@@ -80,13 +161,14 @@ impl Completion {
                     return None;
                 }
             };
-            let Some(file_name) = p.file_name() else {
-                return None;
-            };
+            let file_name = p.file_name()?;
             let file_name = arg_dir.join(file_name);
-            let trailing = if file_name.is_dir() { "/" } else { "" };
-            let file_name = format!("{}{}", file_name.to_string_lossy(), trailing);
-            Some(Completion::new(&file_name, ""))
+            let file_name = file_name.to_string_lossy();
+            if p.is_dir() {
+                Some(Completion::new(&format!("{file_name}/"), "").no_space())
+            } else {
+                Some(Completion::new(&file_name, ""))
+            }
         })
     }
 }
@@ -102,6 +184,9 @@ pub enum Shell {
     Zsh,
     Fish,
     Bash,
+    PowerShell,
+    Elvish,
+    Nushell,
 }
 impl std::str::FromStr for Shell {
     type Err = String;
@@ -110,6 +195,9 @@ impl std::str::FromStr for Shell {
             "zsh" => Shell::Zsh,
             "fish" => Shell::Fish,
             "bash" => Shell::Bash,
+            "powershell" | "pwsh" => Shell::PowerShell,
+            "elvish" => Shell::Elvish,
+            "nushell" | "nu" => Shell::Nushell,
             _ => return Err(format!("Unknown shell {}", s)),
         };
 
@@ -137,10 +225,24 @@ impl std::str::FromStr for Shell {
 pub struct CompletionGroup {
     arg: String,
     comps: Vec<Completion>,
+    quote: Option<Quote>,
 }
 impl CompletionGroup {
     pub(crate) fn new(comps: Vec<Completion>, arg: String) -> Self {
-        CompletionGroup { arg, comps }
+        CompletionGroup {
+            arg,
+            comps,
+            quote: None,
+        }
+    }
+    pub(crate) fn with_quote(mut self, quote: Option<Quote>) -> Self {
+        self.quote = quote;
+        self
+    }
+    /// The quote that's still open in the word being completed, e.g. `git checkout "my fi<TAB>`.
+    /// Only known when completing a raw line with `Command::supplement_line`.
+    pub fn open_quote(&self) -> Option<Quote> {
+        self.quote
     }
     #[doc(hidden)]
     pub fn inner(&self) -> (&[Completion], &str) {
@@ -151,26 +253,86 @@ impl CompletionGroup {
         (self.comps, self.arg)
     }
 
+    /// Print the completions in the format of the shell's script.
+    /// Values are escaped by the rule of each shell, taking the open quote of the current word
+    /// into account, so that things like file names with spaces can be inserted as they are.
     pub fn print(&self, shell: Shell, w: &mut impl Write) -> IoResult<()> {
         match shell {
             Shell::Bash => {
+                let mut no_space = false;
                 for comp in self.comps.iter() {
                     if !comp.value.starts_with(&self.arg) {
                         continue; // If there are multiple candates, bash will not complete :(
                     }
-                    writeln!(w, "{}", comp.value)?; // Bash doesn't allow description
+                    // Bash doesn't allow description
+                    writeln!(w, "{}", escape_posix(comp.split_typed(), self.quote))?;
+                    no_space |= comp.no_space;
+                }
+                // Values never contain a raw tab, so this line can't be mistaken for one
+                if no_space {
+                    writeln!(w, "\tnospace")?;
                 }
             }
             Shell::Fish => {
                 for comp in self.comps.iter() {
-                    let desc = match (comp.description.as_str(), comp.group) {
-                        ("", None) => "",
-                        ("", Some(g)) => g,
-                        (desc, _) => desc,
+                    let desc = comp.description_or_group();
+                    writeln!(w, "{}\t{}", escape_fish(&comp.value), sanitize(desc))?
+                }
+            }
+            Shell::PowerShell => {
+                // Fields of `CompletionResult`: completion text, list item text, result type and tooltip
+                for comp in self.comps.iter() {
+                    let ty = match comp.kind() {
+                        "flag" => "ParameterName",
+                        "command" => "Command",
+                        _ => "ParameterValue",
+                    };
+                    // Tooltip can't be empty
+                    let tooltip = match comp.description_or_group() {
+                        "" => &comp.value,
+                        desc => desc,
+                    };
+                    writeln!(
+                        w,
+                        "{}\t{}\t{}\t{}",
+                        escape_powershell(comp.split_typed(), self.quote),
+                        sanitize(comp.display_text()),
+                        ty,
+                        sanitize(tooltip)
+                    )?
+                }
+            }
+            Shell::Elvish => {
+                // One JSON object per line, to be turned into `edit:complex-candidate`
+                for comp in self.comps.iter() {
+                    let display = match comp.description_or_group() {
+                        "" => comp.display_text().to_owned(),
+                        desc => format!("{} ({})", comp.display_text(), sanitize(desc)),
                     };
-                    writeln!(w, "{}\t{}", comp.value, desc)?
+                    writeln!(
+                        w,
+                        "{{\"value\":{},\"display\":{},\"suffix\":{}}}",
+                        json_str(&comp.value),
+                        json_str(&display),
+                        if comp.no_space { "\"\"" } else { "\" \"" }
+                    )?
                 }
             }
+            Shell::Nushell => {
+                // A table of records for the external completer
+                let records: Vec<_> = self
+                    .comps
+                    .iter()
+                    .map(|comp| {
+                        format!(
+                            "{{\"value\":{},\"description\":{}}}",
+                            json_str(&comp.value),
+                            json_str(comp.description_or_group())
+                        )
+                    })
+                    .collect();
+                writeln!(w, "[{}]", records.join(","))?
+            }
             Shell::Zsh => {
                 let mut groups: Vec<(&str, Vec<&Completion>)> = vec![];
                 for comp in self.comps.iter() {
@@ -189,17 +351,15 @@ impl CompletionGroup {
                 groups.sort_by_key(|(k, v)| (v.len(), *k));
 
                 for (group, comps) in groups.into_iter() {
-                    writeln!(w, "{}", group)?;
+                    writeln!(w, "{}", sanitize(group))?;
                     for comp in comps.into_iter() {
-                        if comp.description.is_empty() {
-                            writeln!(w, "\t{}\t{}", comp.value, comp.value)?
-                        } else {
-                            writeln!(
-                                w,
-                                "\t{}\t{} -- {}",
-                                comp.value, comp.value, comp.description
-                            )?
+                        let value = escape_posix(comp.split_typed(), self.quote);
+                        let mut display = sanitize(comp.display_text());
+                        if !comp.description.is_empty() {
+                            display = format!("{display} -- {}", sanitize(&comp.description));
                         }
+                        let no_space = if comp.no_space { "\tnospace" } else { "" };
+                        writeln!(w, "\t{value}\t{display}{no_space}")?
                     }
                 }
                 writeln!(w, "END")?;
@@ -208,3 +368,326 @@ impl CompletionGroup {
         Ok(())
     }
 }
+
+/// Escape the value for bash and zsh, so that the shell reads it back as it is.
+/// If the word has an open quote, the value is inserted after that quote,
+/// so it's escaped as if it's inside the quote.
+/// Tabs and newlines would break the output format, so they're always written as `$'\t'` and `$'\n'`.
+/// The typed prefix is written as it is.
+fn escape_posix((typed, value): (&str, &str), quote: Option<Quote>) -> String {
+    let mut ret = typed.to_owned();
+    for ch in value.chars() {
+        let ansi = match ch {
+            '\n' => Some("$'\\n'"),
+            '\t' => Some("$'\\t'"),
+            _ => None,
+        };
+        match (quote, ansi) {
+            (None, Some(ansi)) => ret += ansi,
+            (Some(Quote::Single), Some(ansi)) => ret += &format!("'{ansi}'"),
+            (Some(Quote::Double), Some(ansi)) => ret += &format!("\"{ansi}\""),
+            (None, None) => {
+                if " '\"\\$`*?[](){}<>|&;!#^".contains(ch) {
+                    ret.push('\\');
+                }
+                ret.push(ch);
+            }
+            (Some(Quote::Single), None) => match ch {
+                '\'' => ret += "'\\''",
+                _ => ret.push(ch),
+            },
+            (Some(Quote::Double), None) => {
+                if "\"$`\\".contains(ch) {
+                    ret.push('\\');
+                }
+                ret.push(ch);
+            }
+        }
+    }
+    ret
+}
+
+/// Fish escapes the candidate by itself when inserting it, and knows about the open quote.
+/// We only need to take care of tabs and newlines, which would break the output format.
+fn escape_fish(value: &str) -> String {
+    value.replace('\t', "\\t").replace('\n', "\\n")
+}
+
+/// PowerShell doesn't quote the completion text of native commands, so quote it here if needed.
+/// The current word includes its open quote, so the quote is written again.
+/// The typed prefix is kept out of single quotes, which would stop a variable in it from expanding.
+fn escape_powershell((typed, rest): (&str, &str), quote: Option<Quote>) -> String {
+    let needs_quote = |s: &str| {
+        s.is_empty() || s.contains(|c: char| c.is_whitespace() || "'\"`$&|;,(){}<>@#".contains(c))
+    };
+    let value = format!("{typed}{rest}");
+    let has_control = value.contains(|c: char| c.is_control());
+    match quote {
+        None if !typed.is_empty() && !needs_quote(rest) => value,
+        None if typed.is_empty() && !needs_quote(&value) => value,
+        // Single quotes have no escape sequence, so control characters can only be replaced
+        Some(Quote::Single) => format!("'{}'", sanitize(&value).replace('\'', "''")),
+        None if typed.is_empty() && !has_control => format!("'{}'", value.replace('\'', "''")),
+        None | Some(Quote::Double) => {
+            let mut ret = format!("\"{typed}");
+            for ch in rest.chars() {
+                match ch {
+                    '`' | '"' | '$' => ret.push('`'),
+                    '\t' => {
+                        ret += "`t";
+                        continue;
+                    }
+                    '\n' => {
+                        ret += "`n";
+                        continue;
+                    }
+                    _ => (),
+                }
+                ret.push(ch);
+            }
+            ret.push('"');
+            ret
+        }
+    }
+}
+
+/// Print a message for the user instead of completions, e.g. the error of a failed `comp_options`.
+/// `arg` is the word being completed.
+/// - zsh shows it with `_message`.
+/// - fish shows it as the description of a candidate. A second candidate keeps the common prefix
+///   at `arg`, so fish lists both without inserting anything.
+/// - Other shells have no way to show a message without offering it as a candidate,
+///   so they get no completion and the message is only logged.
+pub fn print_message(shell: Shell, arg: &str, msg: &str, w: &mut impl Write) -> IoResult<()> {
+    match shell {
+        Shell::Zsh => {
+            writeln!(w, "MESSAGE\t{}", sanitize(msg))?;
+            writeln!(w, "END")
+        }
+        Shell::Fish => {
+            writeln!(w, "{}ERR\t{}", escape_fish(arg), sanitize(msg))?;
+            writeln!(w, "{}_", escape_fish(arg))
+        }
+        _ => {
+            log::info!("{shell:?} can't show the message: {msg}");
+            CompletionGroup::new(vec![], String::new()).print(shell, w)
+        }
+    }
+}
+
+/// Print the result of completion as a single JSON object, for tools other than shells, e.g. editors.
+/// Errors are reported as objects too, so the output is always parsable:
+/// ```text
+/// {"arg":"--j","quote":null,"completions":[{"value":"--json","display":null,"description":"","group":null,"kind":"flag","no_space":false}]}
+/// {"error":{"kind":"FlagNotFound","message":"flag `xx` is not found"}}
+/// ```
+pub fn print_json(res: &crate::Result<CompletionGroup>, w: &mut impl Write) -> IoResult<()> {
+    let grp = match res {
+        Ok(grp) => grp,
+        Err(err) => {
+            let kind = json_str(err.kind());
+            let message = json_str(&err.to_string());
+            return writeln!(w, "{{\"error\":{{\"kind\":{kind},\"message\":{message}}}}}");
+        }
+    };
+    let records: Vec<_> = grp
+        .comps
+        .iter()
+        .map(|comp| {
+            format!(
+                "{{\"value\":{},\"display\":{},\"description\":{},\"group\":{},\"kind\":{},\"no_space\":{}}}",
+                json_str(&comp.value),
+                comp.display.as_deref().map_or("null".to_owned(), json_str),
+                json_str(&comp.description),
+                comp.group.map_or("null".to_owned(), json_str),
+                json_str(comp.kind()),
+                comp.no_space,
+            )
+        })
+        .collect();
+    let quote = match grp.quote {
+        None => "null",
+        Some(Quote::Single) => "\"single\"",
+        Some(Quote::Double) => "\"double\"",
+    };
+    writeln!(
+        w,
+        "{{\"arg\":{},\"quote\":{},\"completions\":[{}]}}",
+        json_str(&grp.arg),
+        quote,
+        records.join(",")
+    )
+}
+
+fn json_str(s: &str) -> String {
+    let mut ret = String::from('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\t' => ret += "\\t",
+            '\r' => ret += "\\r",
+            ch if ch.is_control() => ret += &format!("\\u{:04x}", ch as u32),
+            ch => ret.push(ch),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Descriptions are only for display, so just replace control characters like tabs and newlines.
+fn sanitize(desc: &str) -> String {
+    desc.replace(|c: char| c.is_control(), " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_posix() {
+        assert_eq!(escape_posix(("", "my file$"), None), "my\\ file\\$");
+        assert_eq!(escape_posix(("", "a\nb"), None), "a$'\\n'b");
+        assert_eq!(
+            escape_posix(("", "it's a $"), Some(Quote::Single)),
+            "it'\\''s a $"
+        );
+        assert_eq!(
+            escape_posix(("", "a\tb"), Some(Quote::Single)),
+            "a'$'\\t''b"
+        );
+        assert_eq!(
+            escape_posix(("", "\"*$ x"), Some(Quote::Double)),
+            "\\\"*\\$ x"
+        );
+        assert_eq!(escape_posix(("$HOME/", "a b"), None), "$HOME/a\\ b");
+    }
+
+    #[test]
+    fn test_print_typed_prefix() {
+        let comps = vec![Completion::new("${HOME}/my dir/", "").typed_prefix(8)];
+        let grp = CompletionGroup::new(comps.clone(), "${HOME}/".to_owned());
+        let mut out = vec![];
+        grp.print(Shell::Bash, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "${HOME}/my\\ dir/\n");
+
+        let mut out = vec![];
+        grp.print(Shell::PowerShell, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\"${HOME}/my dir/\"\t"), "{out}");
+
+        // The typed prefix stays with the typed part after rewriting
+        let comp = comps[0].clone().rewrite(|v| format!("--dir={v}"));
+        assert_eq!(comp.split_typed(), ("--dir=${HOME}/", "my dir/"));
+    }
+
+    #[test]
+    fn test_print() {
+        let comps = vec![
+            Completion::new("a b", "line1\nline2\tx"),
+            Completion::new("c\td", "").group("file").display("c-d"),
+        ];
+        let grp = CompletionGroup::new(comps.clone(), String::new());
+
+        let mut out = vec![];
+        grp.print(Shell::Fish, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "a b\tline1 line2 x\nc\\td\tfile\n");
+
+        let mut out = vec![];
+        grp.print(Shell::Bash, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "a\\ b\nc$'\\t'd\n");
+
+        let mut out = vec![];
+        grp.print(Shell::Nushell, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "[{\"value\":\"a b\",\"description\":\"line1\\nline2\\tx\"},{\"value\":\"c\\td\",\"description\":\"file\"}]\n"
+        );
+
+        let mut out = vec![];
+        grp.print(Shell::PowerShell, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "'a b'\ta b\tParameterValue\tline1 line2 x\n\"c`td\"\tc-d\tParameterValue\tfile\n"
+        );
+
+        let mut out = vec![];
+        print_json(&Ok(grp), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            r#"{"arg":"","quote":null,"completions":[{"value":"a b","display":null,"description":"line1\nline2\tx","group":null,"kind":"value","no_space":false},{"value":"c\td","display":"c-d","description":"","group":"file","kind":"value","no_space":false}]}"#.to_owned() + "\n"
+        );
+        let mut out = vec![];
+        print_json(
+            &Err(crate::error::Error::FlagNotFound("x".to_owned())),
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            r#"{"error":{"kind":"FlagNotFound","message":"flag `x` is not found"}}"#.to_owned()
+                + "\n"
+        );
+        let mut out = vec![];
+        print_message(Shell::Zsh, "x", "git\tnot found", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "MESSAGE\tgit not found\nEND\n");
+        let mut out = vec![];
+        print_message(Shell::Fish, "x", "git\tnot found", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "xERR\tgit not found\nx_\n");
+        for shell in [Shell::Elvish, Shell::Bash] {
+            let mut out = vec![];
+            print_message(shell, "x", "git not found", &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "");
+        }
+
+        let grp = CompletionGroup::new(comps, String::new());
+
+        let grp = grp.with_quote(Some(Quote::Single));
+        let mut out = vec![];
+        grp.print(Shell::Zsh, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "file\n\tc'$'\\t''d\tc-d\noption\n\ta b\ta b -- line1 line2 x\nEND\n"
+        );
+    }
+
+    #[test]
+    fn test_print_no_space() {
+        let comps = vec![
+            Completion::new("--color=", "").no_space(),
+            Completion::new("--count", ""),
+        ];
+        let grp = CompletionGroup::new(comps, "--co".to_owned());
+
+        let mut out = vec![];
+        grp.print(Shell::Bash, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "--color=\n--count\n\tnospace\n");
+
+        let mut out = vec![];
+        grp.print(Shell::Zsh, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "option\n\t--color=\t--color=\tnospace\n\t--count\t--count\nEND\n"
+        );
+
+        let mut out = vec![];
+        grp.print(Shell::Elvish, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "{\"value\":\"--color=\",\"display\":\"--color=\",\"suffix\":\"\"}\n{\"value\":\"--count\",\"display\":\"--count\",\"suffix\":\" \"}\n"
+        );
+    }
+}
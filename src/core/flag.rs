@@ -1,6 +1,6 @@
-use super::{CompOption, parse_flag};
-use crate::completion::CompletionGroup;
+use super::{CompOption, Completer, complete_value, parse_flag, push_value};
 use crate::error::Error;
+use crate::guard::Runner;
 use crate::parsed_flag::ParsedFlag;
 use crate::{Completion, History, Result, id};
 use std::iter::Peekable;
@@ -34,129 +34,236 @@ pub mod flag_type {
     }
     #[doc(hidden)]
     #[derive(Clone, Copy)]
-    pub struct Valued {
+    pub struct Valued<C> {
         pub(crate) id: id::Valued,
         pub(crate) complete_with_equal: CompleteWithEqual,
-        pub(crate) comp_options: CompOption,
+        pub(crate) comp_options: C,
+        pub(crate) min_values: usize,
+        pub(crate) max_values: usize,
+        pub(crate) value_delimiter: Option<char>,
     }
-    impl Valued {
+    impl<C> Valued<C> {
         pub(crate) fn push(&self, history: &mut History, arg: String) {
-            match self.id {
-                id::Valued::Single(id) => history.push_single_val(id, arg),
-                id::Valued::Multi(id) => history.push_multi_val(id, arg),
-            }
+            push_value(history, self.id, self.value_delimiter, arg)
+        }
+        pub(crate) fn complete<Ctx>(
+            &self,
+            runner: &Runner<Ctx>,
+            history: &History,
+            arg: &str,
+            index: usize,
+        ) -> Result<Vec<Completion>>
+        where
+            C: Completer<Ctx>,
+        {
+            let comp_options = &self.comp_options;
+            complete_value(
+                runner,
+                history,
+                self.id,
+                comp_options,
+                self.value_delimiter,
+                arg,
+                index,
+            )
         }
     }
 
+    /// `C` is how the values are completed. It's a `fn` pointer for the const definition,
+    /// and a boxed closure for the runtime one, see `runtime::Flag`.
     #[derive(Clone, Copy)]
-    pub enum Type {
+    pub enum Type<C = CompOption> {
         Bool(Bool),
-        Valued(Valued),
+        Valued(Valued<C>),
     }
-    impl Type {
+    impl<Ctx> Type<CompOption<Ctx>> {
         pub const fn new_bool(id: id::NoVal) -> Self {
             Type::Bool(Bool { id })
         }
         pub const fn new_valued(
             id: id::Valued,
             complete_with_equal: CompleteWithEqual,
-            comp_options: CompOption,
+            comp_options: CompOption<Ctx>,
         ) -> Self {
             Type::Valued(Valued {
                 id,
                 complete_with_equal,
                 comp_options,
+                min_values: 1,
+                max_values: 1,
+                value_delimiter: None,
             })
         }
+        /// For flags that take more than one value in a row, e.g. `--point X Y`.
+        /// Has no effect on boolean flags.
+        pub const fn with_num_values(self, min_values: usize, max_values: usize) -> Self {
+            match self {
+                Type::Bool(_) => self,
+                Type::Valued(valued) => Type::Valued(Valued {
+                    min_values,
+                    max_values,
+                    ..valued
+                }),
+            }
+        }
+        /// For flags that take values like `--features a,b,c`.
+        /// Has no effect on boolean flags.
+        pub const fn with_value_delimiter(self, delimiter: char) -> Self {
+            match self {
+                Type::Bool(_) => self,
+                Type::Valued(valued) => Type::Valued(Valued {
+                    value_delimiter: Some(delimiter),
+                    ..valued
+                }),
+            }
+        }
     }
 }
 
 use flag_type::*;
 
-pub struct Flag {
-    pub ty: Type,
+/// The flag value under the cursor and its index, see `FlagDef::take_values`.
+pub(crate) type CursorValue<'a, C> = (&'a Valued<C>, usize, String);
+
+pub struct Flag<Ctx = ()> {
+    pub ty: Type<CompOption<Ctx>>,
     pub short: &'static [char],
     pub long: &'static [&'static str],
     pub description: &'static str,
     pub once: bool,
+    /// Hidden flags are still parsed, but only shown when nothing else matches.
+    pub hidden: bool,
+    /// Flags and args that can't be used together with this flag.
+    pub conflicts: &'static [id::Any],
+    /// Flags and args that must be present before this flag is listed, like clap's `requires`.
+    /// clap doesn't expose it, so it's set by `Config::requires` or `runtime::Command::requires`.
+    pub requires: &'static [id::Any],
+    /// If the flag is required for the command line to be valid, i.e. clap's `required(true)`.
+    /// Missing required flags are listed first.
+    pub required: bool,
+}
+
+impl<Ctx: 'static> Flag<Ctx> {
+    pub fn get_description(&self) -> &str {
+        FlagDef::get_description(self)
+    }
 }
 
-impl Flag {
-    pub fn get_description(&self) -> &'static str {
-        if !self.description.is_empty() {
-            return self.description;
+/// The flag definition that the parser works on, see `CommandDef`.
+pub(crate) trait FlagDef {
+    type Ctx;
+    type Comp: Completer<Self::Ctx>;
+
+    fn ty(&self) -> &Type<Self::Comp>;
+    fn short(&self) -> &[char];
+    fn long(&self) -> impl Iterator<Item = &str>;
+    fn description(&self) -> &str;
+    fn once(&self) -> bool;
+    fn hidden(&self) -> bool;
+    fn conflicts(&self) -> &[id::Any];
+    fn requires(&self) -> &[id::Any];
+    fn required(&self) -> bool;
+
+    fn get_description(&self) -> &str {
+        if !self.description().is_empty() {
+            return self.description();
         }
-        if let Some(long) = self.long.get(0) {
-            return *long;
+        if let Some(long) = self.long().next() {
+            return long;
         }
         ""
     }
-    pub(super) fn gen_completion(&self, is_long: Option<bool>) -> impl Iterator<Item = Completion> {
+    fn gen_completion(&self, is_long: Option<bool>) -> impl Iterator<Item = Completion> {
         let (long, short) = match is_long {
-            None => (self.long, self.short),
-            Some(true) => (self.long, &[] as &[char]),
-            Some(false) => (&[] as &[&str], self.short),
+            None => (true, true),
+            Some(true) => (true, false),
+            Some(false) => (false, true),
         };
 
-        let long = long
-            .iter()
+        let long = self
+            .long()
+            .filter(move |_| long)
             .map(|l| Completion::new(&format!("--{l}"), self.get_description()));
-        let short = short
+        let short = self
+            .short()
             .iter()
+            .filter(move |_| short)
             .map(|s| Completion::new(&format!("-{s}"), self.get_description()));
         let iter = long.chain(short).take(1);
         iter.flat_map(|mut comp| {
             let mut more = None;
-            match self.ty {
-                Type::Valued(Valued {
-                    complete_with_equal,
-                    ..
-                }) => match complete_with_equal {
+            if let Type::Valued(Valued {
+                complete_with_equal,
+                ..
+            }) = self.ty()
+            {
+                match complete_with_equal {
                     CompleteWithEqual::NoNeed => (),
                     CompleteWithEqual::Must => {
                         comp.value += "=";
+                        comp.no_space = true;
                     }
                     CompleteWithEqual::Optional => {
                         more = Some(comp.clone());
                         comp.value += "=";
+                        comp.no_space = true;
                     }
-                },
-                _ => (),
+                }
             }
-            std::iter::once(comp).chain(more.into_iter())
+            std::iter::once(comp).chain(more)
         })
     }
 
-    pub(super) fn exists_in_history(&self, history: &History) -> bool {
-        match self.ty {
-            Type::Bool(Bool { id, .. }) => history.find(id).is_some(),
+    fn exists_in_history(&self, history: &History) -> bool {
+        history.contains(self.any_id())
+    }
+
+    fn conflicts_with_history(&self, history: &History) -> bool {
+        self.conflicts().iter().any(|id| history.contains(*id))
+    }
+
+    fn requires_met(&self, history: &History) -> bool {
+        self.requires().iter().all(|id| history.contains(*id))
+    }
+
+    fn is_missing_required(&self, history: &History) -> bool {
+        self.required() && !self.exists_in_history(history)
+    }
+
+    fn any_id(&self) -> id::Any {
+        match self.ty() {
+            Type::Bool(Bool { id, .. }) => id::Any::No(*id),
             Type::Valued(Valued {
                 id: id::Valued::Single(id),
                 ..
-            }) => history.find(id).is_some(),
+            }) => id::Any::Single(*id),
             Type::Valued(Valued {
                 id: id::Valued::Multi(id),
                 ..
-            }) => history.find(id).is_some(),
+            }) => id::Any::Multi(*id),
         }
     }
 
-    pub(super) fn supplement(
+    /// Take the values of this flag from `args` and push them into history.
+    /// With `stop_at_last`, the last word of `args` is the value under the cursor. It's returned
+    /// along with its index instead of being pushed, so that the caller can complete it.
+    fn take_values(
         &self,
         history: &mut History,
         args: &mut Peekable<impl Iterator<Item = String>>,
-    ) -> Result<Option<CompletionGroup>> {
-        let valued = match self.ty {
+        stop_at_last: bool,
+    ) -> Result<Option<CursorValue<'_, Self::Comp>>> {
+        let valued = match self.ty() {
             Type::Bool(inner) => {
                 inner.push(history);
                 return Ok(None);
             }
             Type::Valued(inner) => inner,
         };
-        let name = self.id();
+        let name = self.name();
 
         match valued.complete_with_equal {
-            CompleteWithEqual::Must => return Err(Error::RequiresEqual(name)),
+            CompleteWithEqual::Must => return Err(Error::RequiresEqual(name.to_owned())),
             CompleteWithEqual::NoNeed => (),
             CompleteWithEqual::Optional => {
                 // TODO: Maybe one day clap will tell us.
@@ -169,28 +276,41 @@ impl Flag {
             }
         }
 
-        let arg = args.next().unwrap();
-        match parse_flag(&arg, false) {
-            ParsedFlag::NotFlag | ParsedFlag::Empty | ParsedFlag::SingleDash => (),
-            ParsedFlag::DoubleDash | ParsedFlag::Long { .. } | ParsedFlag::Shorts => {
-                log::warn!(
-                    "`--{name} {arg}` is invalid. Maybe you should write it like `--{name}={arg}",
-                );
-                return Err(Error::FlagNoValue(name));
+        // Even if the flag is optional, we still treat the next arg as its value. See `CompleteWithEqual`.
+        let min_values = valued.min_values.max(1);
+        let mut index = 0;
+        while index < valued.max_values {
+            let Some(arg) = args.peek() else {
+                break;
+            };
+            match parse_flag(arg, false) {
+                ParsedFlag::NotFlag | ParsedFlag::Empty | ParsedFlag::SingleDash => (),
+                ParsedFlag::DoubleDash | ParsedFlag::Long { .. } | ParsedFlag::Shorts => {
+                    if index >= min_values {
+                        log::debug!("flag {name} ends with {index} values");
+                        break;
+                    }
+                    log::warn!(
+                        "`--{name} {arg}` is invalid. Maybe you should write it like `--{name}={arg}",
+                    );
+                    return Err(Error::FlagNoValue(name.to_owned()));
+                }
             }
-        }
 
-        if args.peek().is_none() {
-            let group = CompletionGroup::new((valued.comp_options)(&history, &arg), arg);
-            return Ok(Some(group));
-        }
+            let arg = args.next().unwrap();
+            if stop_at_last && args.peek().is_none() {
+                return Ok(Some((valued, index, arg)));
+            }
 
-        valued.push(history, arg);
+            valued.push(history, arg);
+            index += 1;
+        }
         Ok(None)
     }
 
-    pub(crate) fn id(&self) -> &'static str {
-        match self.ty {
+    /// The name for logs and errors, e.g. `git_dir` for the `--git-dir` flag.
+    fn name(&self) -> &str {
+        match self.ty() {
             Type::Bool(Bool { id, .. }) => id.1,
             Type::Valued(Valued {
                 id: id::Valued::Single(id),
@@ -203,3 +323,36 @@ impl Flag {
         }
     }
 }
+
+impl<Ctx: 'static> FlagDef for Flag<Ctx> {
+    type Ctx = Ctx;
+    type Comp = CompOption<Ctx>;
+
+    fn ty(&self) -> &Type<CompOption<Ctx>> {
+        &self.ty
+    }
+    fn short(&self) -> &[char] {
+        self.short
+    }
+    fn long(&self) -> impl Iterator<Item = &str> {
+        self.long.iter().copied()
+    }
+    fn description(&self) -> &str {
+        self.description
+    }
+    fn once(&self) -> bool {
+        self.once
+    }
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+    fn conflicts(&self) -> &[id::Any] {
+        self.conflicts
+    }
+    fn requires(&self) -> &[id::Any] {
+        self.requires
+    }
+    fn required(&self) -> bool {
+        self.required
+    }
+}
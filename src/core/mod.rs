@@ -1,4 +1,5 @@
 mod flag;
+pub(crate) use flag::FlagDef;
 pub use flag::{CompleteWithEqual, Flag, flag_type};
 
 use std::iter::Peekable;
@@ -6,37 +7,167 @@ use std::iter::Peekable;
 use crate::arg_context::ArgsContext;
 use crate::completion::CompletionGroup;
 use crate::error::Error;
+use crate::guard::{Guard, Runner, SharedComp, spawn};
 use crate::id;
+use crate::line::Line;
 use crate::parsed_flag::ParsedFlag;
-use crate::{Completion, History, Result};
+use crate::{CompResult, Completion, History, Result};
+use std::sync::Arc;
 
-type CompOption = fn(&History, &str) -> Vec<Completion>;
+/// `Ctx` is the context given to `Command::supplement_with_ctx`, see `Config::ctx_type`.
+/// The last parameter is the index of the value being completed, for flags and args that take
+/// more than one value. e.g. `--point 1 <TAB>` gives 1, while `--point <TAB>` gives 0.
+type CompOption<Ctx = ()> = fn(&Ctx, &History, &str, usize) -> CompResult;
 
-pub struct Arg {
+/// How the values of a flag or arg are completed.
+pub(crate) trait Completer<Ctx> {
+    fn complete(
+        &self,
+        ctx: &Ctx,
+        history: &History,
+        value: &str,
+        index: usize,
+    ) -> Result<Vec<Completion>>;
+    fn shared(&self) -> SharedComp<Ctx>;
+}
+impl<Ctx: 'static> Completer<Ctx> for CompOption<Ctx> {
+    fn complete(
+        &self,
+        ctx: &Ctx,
+        history: &History,
+        value: &str,
+        index: usize,
+    ) -> Result<Vec<Completion>> {
+        self(ctx, history, value, index).map_err(|err| Error::Completer(err.0))
+    }
+    fn shared(&self) -> SharedComp<Ctx> {
+        Arc::new(*self)
+    }
+}
+
+pub struct Arg<Ctx = ()> {
     pub id: id::Valued,
-    pub comp_options: CompOption,
+    pub comp_options: CompOption<Ctx>,
     pub max_values: usize,
+    /// Only reachable after the `--` separator, e.g. clap's `last(true)`.
+    pub last: bool,
+    /// Split a single word into multiple values, e.g. clap's `value_delimiter(',')`.
+    pub value_delimiter: Option<char>,
+    /// If the arg is required for the command line to be valid. A missing required arg is listed first.
+    pub required: bool,
+}
+
+/// The arg definition that the parser works on, see `CommandDef`.
+pub(crate) trait ArgDef {
+    type Ctx;
+    type Comp: Completer<Self::Ctx>;
+
+    fn id(&self) -> id::Valued;
+    fn comp_options(&self) -> &Self::Comp;
+    fn max_values(&self) -> usize;
+    fn last(&self) -> bool;
+    fn value_delimiter(&self) -> Option<char>;
+    fn required(&self) -> bool;
+}
+impl<Ctx: 'static> ArgDef for Arg<Ctx> {
+    type Ctx = Ctx;
+    type Comp = CompOption<Ctx>;
+
+    fn id(&self) -> id::Valued {
+        self.id
+    }
+    fn comp_options(&self) -> &CompOption<Ctx> {
+        &self.comp_options
+    }
+    fn max_values(&self) -> usize {
+        self.max_values
+    }
+    fn last(&self) -> bool {
+        self.last
+    }
+    fn value_delimiter(&self) -> Option<char> {
+        self.value_delimiter
+    }
+    fn required(&self) -> bool {
+        self.required
+    }
 }
 
 /// The object to represent a command.
 /// Usually this object is a constant created by code-gen,
 /// and user can just call `supplement` function for CLI completion.
-pub struct Command {
+pub struct Command<Ctx: 'static = ()> {
     pub id: id::NoVal,
     pub name: &'static str,
+    /// Aliases that are shown in completion, e.g. `co` for `checkout`.
+    pub aliases: &'static [&'static str],
+    /// Aliases that are accepted but never shown in completion.
+    pub hidden_aliases: &'static [&'static str],
     pub description: &'static str,
-    pub all_flags: &'static [Flag],
-    pub args: &'static [Arg],
-    pub commands: &'static [Command],
+    pub all_flags: &'static [Flag<Ctx>],
+    pub args: &'static [Arg<Ctx>],
+    pub commands: &'static [Command<Ctx>],
+    /// Hidden commands are still parsed, but only shown when nothing else matches.
+    pub hidden: bool,
+}
+
+/// What a word means to the parser, see `CommandDef::parse_word`.
+pub(crate) enum Parsed<'a, Cmd, Comp> {
+    Next,
+    /// A subcommand, the rest of the words belong to it.
+    Command(&'a Cmd),
+    /// The value of a flag under the cursor, along with its index.
+    FlagValue(&'a flag_type::Valued<Comp>, usize, String),
 }
 
-fn supplement_arg(history: &mut History, ctx: &mut ArgsContext, arg: String) -> Result {
-    let Some(arg_obj) = ctx.next_arg() else {
+fn supplement_arg<A: ArgDef>(
+    history: &mut History,
+    args_ctx: &mut ArgsContext<A>,
+    arg: String,
+) -> Result {
+    let Some(arg_obj) = args_ctx.next_arg() else {
         return Err(Error::UnexpectedArg(arg));
     };
-    history.push_arg(arg_obj.id, arg);
+    push_value(history, arg_obj.id(), arg_obj.value_delimiter(), arg);
     Ok(())
 }
+/// Push the value of a flag or arg into history.
+/// Values like `a,b,c` are split by the delimiter, if any.
+fn push_value(history: &mut History, id: id::Valued, delimiter: Option<char>, value: String) {
+    match (id, delimiter) {
+        (id::Valued::Multi(id), Some(delimiter)) => {
+            for value in value.split(delimiter) {
+                history.push_multi_val(id, value.to_owned());
+            }
+        }
+        _ => history.push_arg(id, value),
+    }
+}
+/// Complete the value of a flag or arg.
+/// For values like `a,b,<TAB>`, only the segment after the last delimiter is passed to `comp_options`,
+/// the results are prefixed with the typed part, and values already listed are skipped.
+fn complete_value<Ctx>(
+    runner: &Runner<Ctx>,
+    history: &History,
+    id: id::Valued,
+    comp_options: &impl Completer<Ctx>,
+    delimiter: Option<char>,
+    value: &str,
+    index: usize,
+) -> Result<Vec<Completion>> {
+    let Some((delimiter, (prefix, cur))) = delimiter.and_then(|d| Some((d, value.rsplit_once(d)?)))
+    else {
+        return runner.complete(id, comp_options, history, value, index);
+    };
+    let listed: Vec<_> = prefix.split(delimiter).collect();
+    let comps = runner
+        .complete(id, comp_options, history, cur, index)?
+        .into_iter()
+        .filter(|c| !listed.contains(&c.value.as_str()))
+        .map(|c| c.rewrite(|v| format!("{prefix}{delimiter}{v}")))
+        .collect();
+    Ok(comps)
+}
 fn parse_flag(s: &str, disable_flag: bool) -> ParsedFlag<'_> {
     if disable_flag {
         log::info!("flag is disabled: {}", s);
@@ -46,6 +177,35 @@ fn parse_flag(s: &str, disable_flag: bool) -> ParsedFlag<'_> {
     }
 }
 
+/// Put missing required items in the "required" group, unless the user has given them a group.
+fn mark_required(comp: Completion, required: bool) -> Completion {
+    if required && comp.group.is_none() {
+        comp.group("required")
+    } else {
+        comp
+    }
+}
+
+/// Hidden items are left out, unless the user has typed a prefix that matches
+/// some hidden items but none of the visible ones, e.g. `--deb<TAB>` => `--debug`.
+fn filter_hidden(comps: Vec<(bool, Completion)>, typed: &str) -> Vec<Completion> {
+    let (hidden, visible): (Vec<_>, Vec<_>) = comps.into_iter().partition(|(h, _)| *h);
+    let is_trivial = typed.trim_start_matches('-').is_empty();
+    let matches = |c: &Completion| c.value.starts_with(typed);
+    if !is_trivial
+        && !visible.iter().any(|(_, c)| matches(c))
+        && hidden.iter().any(|(_, c)| matches(c))
+    {
+        log::info!("`{typed}` only matches hidden items");
+        return hidden
+            .into_iter()
+            .map(|(_, c)| c)
+            .filter(|c| matches(c))
+            .collect();
+    }
+    visible.into_iter().map(|(_, c)| c).collect()
+}
+
 fn check_no_flag(v: Vec<Completion>) -> Result<Vec<Completion>> {
     if v.is_empty() {
         return Err(Error::UnexpectedFlag);
@@ -63,10 +223,13 @@ impl Command {
     ///     Command {
     ///         id: id::NoVal::new(0, name),
     ///         name,
+    ///         aliases: &[],
+    ///         hidden_aliases: &[],
     ///         description: "",
     ///         all_flags: &[],
     ///         args: &[],
     ///         commands: subcmd,
+    ///         hidden: false,
     ///     }
     /// }
     ///
@@ -88,7 +251,250 @@ impl Command {
     pub fn supplement_with_history(
         &self,
         history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_with_ctx(&(), history, args)
+    }
+
+    /// Like `supplement`, but complete the word at `cursor` instead of the last one.
+    /// e.g. for `git checkout <TAB> --json`, the cursor is 2.
+    ///
+    /// Words after the cursor are still parsed, so that flags typed later in the line
+    /// are taken into account. If `offset` is given, only the part of the word before
+    /// this byte offset is completed. The cursor can also be right after the last word,
+    /// which means completing a new empty word.
+    pub fn supplement_at(
+        &self,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        let mut history = History::default();
+        self.supplement_at_with_history(&mut history, args, cursor, offset)
+    }
+
+    pub fn supplement_at_with_history(
+        &self,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_at_with_ctx(&(), history, args, cursor, offset)
+    }
+
+    /// Like `supplement_at`, but take the raw command line and the byte offset of the cursor,
+    /// e.g. `COMP_LINE` and `COMP_POINT` in bash.
+    /// The line is split into words with `Line`, which knows about quotes, escapes and comments.
+    /// ```no_run
+    /// # use supplements::Command;
+    /// # fn create_cmd() -> Command {
+    /// #     unimplemented!()
+    /// # }
+    /// let cmd: Command = create_cmd();
+    /// let grp = cmd.supplement_line(r#"git checkout "my fi"#, 19).unwrap();
+    /// assert!(grp.open_quote().is_some());
+    /// ```
+    pub fn supplement_line(&self, line: &str, cursor: usize) -> Result<CompletionGroup> {
+        let mut history = History::default();
+        self.supplement_line_with_ctx(&(), &mut history, line, cursor)
+    }
+}
+
+impl<Ctx> Command<Ctx> {
+    /// Like `supplement_with_history`, but `ctx` is passed to every `comp_options`,
+    /// so the completers can share things like an opened repository or a config file.
+    /// ```no_run
+    /// # use supplements::{Command, History};
+    /// struct Repo;
+    /// # fn create_cmd() -> Command<Repo> {
+    /// #     unimplemented!()
+    /// # }
+    /// let cmd: Command<Repo> = create_cmd();
+    /// let args = ["git", "checkout", ""].iter().map(|s| s.to_string());
+    /// let grp = cmd.supplement_with_ctx(&Repo, &mut History::default(), args).unwrap();
+    /// ```
+    pub fn supplement_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_with_trailing(&Runner::new(ctx), history, args, &[])
+    }
+
+    /// Like `supplement_at_with_history`, but with a context. See `supplement_with_ctx`.
+    pub fn supplement_at_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_at_with_runner(&Runner::new(ctx), history, args, cursor, offset)
+    }
+
+    /// Like `supplement_line`, but with a context. See `supplement_with_ctx`.
+    pub fn supplement_line_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        line: &str,
+        cursor: usize,
+    ) -> Result<CompletionGroup> {
+        self.supplement_line_with_runner(&Runner::new(ctx), history, line, cursor)
+    }
+
+    /// Like `supplement_with_ctx`, but every `comp_options` runs under the `guard`,
+    /// so a slow or panicking one falls back to something else instead of taking down the completion.
+    /// ```no_run
+    /// # use supplements::{Command, Fallback, Guard, History};
+    /// # use std::time::Duration;
+    /// # fn create_cmd() -> Command {
+    /// #     unimplemented!()
+    /// # }
+    /// let cmd: Command = create_cmd();
+    /// let guard = Guard::default().timeout(Duration::from_millis(200));
+    /// let args = ["git", "checkout", ""].iter().map(|s| s.to_string());
+    /// let grp = cmd.supplement_with_guard(&guard, &(), &mut History::default(), args);
+    /// ```
+    pub fn supplement_with_guard(
+        &self,
+        guard: &Guard,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup>
+    where
+        Ctx: Clone + Send,
+    {
+        let runner = Runner::guarded(ctx, guard, spawn);
+        self.supplement_with_trailing(&runner, history, args, &[])
+    }
+
+    /// Like `supplement_line_with_ctx`, but with a guard. See `supplement_with_guard`.
+    pub fn supplement_line_with_guard(
+        &self,
+        guard: &Guard,
+        ctx: &Ctx,
+        history: &mut History,
+        line: &str,
+        cursor: usize,
+    ) -> Result<CompletionGroup>
+    where
+        Ctx: Clone + Send,
+    {
+        let runner = Runner::guarded(ctx, guard, spawn);
+        self.supplement_line_with_runner(&runner, history, line, cursor)
+    }
+}
+
+impl<Ctx> CommandDef for Command<Ctx> {
+    type Ctx = Ctx;
+    type Flag = Flag<Ctx>;
+    type Arg = Arg<Ctx>;
+
+    fn id(&self) -> id::NoVal {
+        self.id
+    }
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn aliases(&self) -> impl Iterator<Item = &str> {
+        self.aliases.iter().copied()
+    }
+    fn hidden_aliases(&self) -> impl Iterator<Item = &str> {
+        self.hidden_aliases.iter().copied()
+    }
+    fn description(&self) -> &str {
+        self.description
+    }
+    fn all_flags(&self) -> &[Flag<Ctx>] {
+        self.all_flags
+    }
+    fn args(&self) -> &[Arg<Ctx>] {
+        self.args
+    }
+    fn commands(&self) -> &[Command<Ctx>] {
+        self.commands
+    }
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+}
+
+/// The command definition that the parser works on.
+/// It's implemented by the const `Command` created by code-gen, and also by `runtime::Command`,
+/// so both of them share the same parsing logic.
+pub(crate) trait CommandDef: Sized {
+    type Ctx;
+    type Flag: FlagDef<Ctx = Self::Ctx>;
+    type Arg: ArgDef<Ctx = Self::Ctx>;
+
+    fn id(&self) -> id::NoVal;
+    fn name(&self) -> &str;
+    fn aliases(&self) -> impl Iterator<Item = &str>;
+    fn hidden_aliases(&self) -> impl Iterator<Item = &str>;
+    fn description(&self) -> &str;
+    fn all_flags(&self) -> &[Self::Flag];
+    fn args(&self) -> &[Self::Arg];
+    fn commands(&self) -> &[Self];
+    fn hidden(&self) -> bool;
+
+    fn supplement_at_with_runner(
+        &self,
+        runner: &Runner<Self::Ctx>,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        let mut args: Vec<_> = args.collect();
+        if cursor == 0 || cursor > args.len() {
+            return Err(Error::CursorOutOfRange(cursor));
+        }
+        if cursor == args.len() {
+            args.push(String::new());
+        }
+        let trailing = args.split_off(cursor + 1);
+        if let Some(offset) = offset {
+            let word = &mut args[cursor];
+            if word.is_char_boundary(offset) {
+                word.truncate(offset);
+            } else {
+                log::warn!("offset {offset} is not in the word `{word}`, ignore it");
+            }
+        }
+        log::debug!(
+            "complete {:?} with trailing words {:?}",
+            args[cursor],
+            trailing
+        );
+        self.supplement_with_trailing(runner, history, args.into_iter(), &trailing)
+    }
+
+    fn supplement_line_with_runner(
+        &self,
+        runner: &Runner<Self::Ctx>,
+        history: &mut History,
+        line: &str,
+        cursor: usize,
+    ) -> Result<CompletionGroup> {
+        let line = Line::new(line, cursor);
+        log::debug!("line = {:?}", line);
+        let args = line.words.into_iter();
+        let offset = Some(line.offset);
+        let grp = self.supplement_at_with_runner(runner, history, args, line.cursor, offset)?;
+        Ok(grp.with_quote(line.quote))
+    }
+
+    fn supplement_with_trailing(
+        &self,
+        runner: &Runner<Self::Ctx>,
+        history: &mut History,
         mut args: impl Iterator<Item = String>,
+        trailing: &[String],
     ) -> Result<CompletionGroup> {
         args.next(); // ignore the first arg which is the program's name
 
@@ -97,98 +503,195 @@ impl Command {
             return Err(Error::ArgsTooShort);
         }
 
-        self.supplement_recur(&mut None, history, &mut args)
+        self.supplement_recur(runner, &mut None, history, &mut args, trailing)
     }
 
-    fn doing_external(&self, ctx: &ArgsContext) -> bool {
-        let has_subcmd = !self.commands.is_empty();
+    fn is_named(&self, name: &str) -> bool {
+        self.name() == name
+            || self.aliases().any(|a| a == name)
+            || self.hidden_aliases().any(|a| a == name)
+    }
+    fn gen_completion(&self) -> impl Iterator<Item = Completion> {
+        std::iter::once(self.name())
+            .chain(self.aliases())
+            .map(|name| Completion::new(name, self.description()).group("command"))
+    }
+
+    fn doing_external(&self, ctx: &ArgsContext<Self::Arg>) -> bool {
+        let has_subcmd = !self.commands().is_empty();
         has_subcmd && ctx.has_seen_arg()
     }
-    fn flags(&self, history: &History) -> impl Iterator<Item = &Flag> {
-        self.all_flags.iter().filter(|f| {
-            if !f.once {
+    /// After `--` or any positional arg, no more subcommand is possible.
+    fn allow_subcmd(&self, ctx: &ArgsContext<Self::Arg>) -> bool {
+        !ctx.has_seen_arg() && !ctx.is_double_dashed()
+    }
+    fn disable_flag(&self, ctx: &ArgsContext<Self::Arg>) -> bool {
+        self.doing_external(ctx) || ctx.is_double_dashed()
+    }
+    fn flags(&self, history: &History) -> impl Iterator<Item = &Self::Flag> {
+        self.all_flags().iter().filter(|f| {
+            if !f.once() {
                 true
             } else {
                 let exists = f.exists_in_history(history);
                 if exists {
-                    log::debug!("flag {:?} already exists", f.id());
+                    log::debug!("flag {:?} already exists", f.name());
                 }
                 !exists
             }
         })
     }
 
-    fn find_flag<F: FnMut(&Flag) -> bool>(
+    /// Conflicts are checked in both directions,
+    /// because a global flag is shared by all subcommands and can't know every conflict of its own.
+    fn is_conflicted(&self, flag: &Self::Flag, history: &History) -> bool {
+        if flag.conflicts_with_history(history) {
+            return true;
+        }
+        let id = flag.any_id();
+        self.all_flags()
+            .iter()
+            .any(|f| f.conflicts().contains(&id) && f.exists_in_history(history))
+    }
+
+    fn flag_completions(
+        &self,
+        history: &History,
+        is_long: Option<bool>,
+        typed: &str,
+    ) -> Vec<Completion> {
+        // Conflicts and requirements only matter when listing. A flag that's already typed still parses.
+        let comps = self
+            .flags(history)
+            .filter(|f| {
+                let conflicted = self.is_conflicted(f, history);
+                if conflicted {
+                    log::debug!("flag {:?} conflicts with history", f.name());
+                }
+                !conflicted
+            })
+            .filter(|f| {
+                let met = f.requires_met(history);
+                if !met {
+                    log::debug!("requirement of flag {:?} is not met", f.name());
+                }
+                met
+            })
+            .flat_map(|f| {
+                let required = f.is_missing_required(history);
+                f.gen_completion(is_long)
+                    .map(move |c| (required, f.hidden(), mark_required(c, required)))
+            });
+        // Steer the user to a valid command line by showing missing required flags first.
+        // The sort is stable, and `filter_hidden` keeps the order.
+        let mut comps: Vec<_> = comps.collect();
+        comps.sort_by_key(|(required, ..)| !required);
+        let comps = comps.into_iter().map(|(_, h, c)| (h, c)).collect();
+        filter_hidden(comps, typed)
+    }
+
+    fn find_flag<F: FnMut(&Self::Flag) -> bool>(
         &self,
         arg: &str,
         history: &History,
         mut filter: F,
-    ) -> Result<&Flag> {
+    ) -> Result<&Self::Flag> {
         match self.flags(history).find(|f| filter(f)) {
             Some(flag) => Ok(flag),
             None => Err(Error::FlagNotFound(arg.to_owned())),
         }
     }
 
-    fn find_long_flag(&self, flag: &str, history: &History) -> Result<&Flag> {
-        self.find_flag(flag, history, |f| f.long.iter().any(|l| *l == flag))
+    fn find_long_flag(&self, flag: &str, history: &History) -> Result<&Self::Flag> {
+        self.find_flag(flag, history, |f| f.long().any(|l| l == flag))
     }
-    fn find_short_flag(&self, flag: char, history: &History) -> Result<&Flag> {
-        self.find_flag(&flag.to_string(), history, |f| {
-            f.short.iter().any(|s| *s == flag)
-        })
+    fn find_short_flag(&self, flag: char, history: &History) -> Result<&Self::Flag> {
+        self.find_flag(&flag.to_string(), history, |f| f.short().contains(&flag))
     }
 
-    fn supplement_recur(
-        &self,
-        args_ctx_opt: &mut Option<ArgsContext<'_>>,
+    fn supplement_recur<'s>(
+        &'s self,
+        runner: &Runner<Self::Ctx>,
+        args_ctx_opt: &mut Option<ArgsContext<'s, Self::Arg>>,
         history: &mut History,
         args: &mut Peekable<impl Iterator<Item = String>>,
+        trailing: &[String],
     ) -> Result<CompletionGroup> {
         let arg = args.next().unwrap();
 
         let args_ctx = if let Some(ctx) = args_ctx_opt {
             ctx
         } else {
-            *args_ctx_opt = Some(ArgsContext::new(&self.args));
+            *args_ctx_opt = Some(ArgsContext::new(self.args()));
             args_ctx_opt.as_mut().unwrap()
         };
 
         if args.peek().is_none() {
-            return self.supplement_last(args_ctx, history, arg);
+            let takes_slot = match parse_flag(&arg, self.disable_flag(args_ctx)) {
+                ParsedFlag::Empty | ParsedFlag::NotFlag | ParsedFlag::SingleDash => true,
+                ParsedFlag::DoubleDash | ParsedFlag::Long { .. } | ParsedFlag::Shorts => false,
+            };
+            self.parse_trailing(args_ctx, history, trailing, takes_slot);
+            return self.supplement_last(runner, args_ctx, history, arg);
+        }
+
+        match self.parse_word(args_ctx, history, arg, args, true)? {
+            Parsed::Next => (),
+            Parsed::Command(command) => {
+                history.push_no_val(command.id());
+                return command.supplement_recur(runner, &mut None, history, args, trailing);
+            }
+            Parsed::FlagValue(valued, index, value) => {
+                self.parse_trailing(args_ctx, history, trailing, false);
+                let comps = valued.complete(runner, history, &value, index)?;
+                return Ok(CompletionGroup::new(comps, value));
+            }
         }
 
+        self.supplement_recur(runner, args_ctx_opt, history, args, trailing)
+    }
+
+    /// Parse a single word, along with the flag values that follow it, and push them into history.
+    /// A subcommand is returned without being pushed. See `FlagDef::take_values` for `stop_at_last`.
+    fn parse_word<'s>(
+        &'s self,
+        args_ctx: &mut ArgsContext<'s, Self::Arg>,
+        history: &mut History,
+        arg: String,
+        args: &mut Peekable<impl Iterator<Item = String>>,
+        stop_at_last: bool,
+    ) -> Result<Parsed<'s, Self, <Self::Flag as FlagDef>::Comp>> {
         macro_rules! handle_flag {
-            ($flag:expr, $equal:expr, $history:expr) => {
+            ($flag:expr, $equal:expr) => {
                 if let Some(equal) = $equal {
-                    match $flag.ty {
-                        flag_type::Type::Valued(flag) => flag.push($history, equal.to_string()),
+                    match $flag.ty() {
+                        flag_type::Type::Valued(flag) => flag.push(history, equal.to_string()),
                         _ => return Err(Error::BoolFlagEqualsValue(arg)),
                     }
-                } else {
-                    let res = $flag.supplement($history, args)?;
-                    if let Some(res) = res {
-                        return Ok(res);
-                    }
+                } else if let Some((valued, index, value)) =
+                    $flag.take_values(history, args, stop_at_last)?
+                {
+                    return Ok(Parsed::FlagValue(valued, index, value));
                 }
             };
         }
 
-        match parse_flag(&arg, self.doing_external(args_ctx)) {
-            ParsedFlag::SingleDash | ParsedFlag::DoubleDash | ParsedFlag::Empty => {
+        match parse_flag(&arg, self.disable_flag(args_ctx)) {
+            ParsedFlag::SingleDash | ParsedFlag::Empty => {
                 supplement_arg(history, args_ctx, arg)?;
             }
+            ParsedFlag::DoubleDash => {
+                log::info!("end of options, treat everything after as args");
+                args_ctx.double_dash();
+            }
             ParsedFlag::NotFlag => {
-                let command = if args_ctx.has_seen_arg() {
-                    None
+                let command = if self.allow_subcmd(args_ctx) {
+                    self.commands().iter().find(|c| c.is_named(&arg))
                 } else {
-                    self.commands.iter().find(|c| arg == c.name)
+                    None
                 };
                 match command {
-                    Some(command) => {
-                        history.push_no_val(command.id);
-                        return command.supplement_recur(&mut None, history, args);
-                    }
+                    Some(command) => return Ok(Parsed::Command(command)),
                     None => {
                         log::info!("No subcommand. Try fallback args.");
                         supplement_arg(history, args_ctx, arg)?;
@@ -197,73 +700,119 @@ impl Command {
             }
             ParsedFlag::Long { body, equal } => {
                 let flag = self.find_long_flag(body, history)?;
-                handle_flag!(flag, equal, history);
+                handle_flag!(flag, equal);
             }
             ParsedFlag::Shorts => {
                 let resolved = self.resolve_shorts(history, &arg)?;
-                handle_flag!(resolved.last_flag, resolved.value, history);
+                handle_flag!(resolved.last_flag, resolved.value);
             }
         }
+        Ok(Parsed::Next)
+    }
 
-        self.supplement_recur(args_ctx_opt, history, args)
+    /// Parse the words after the cursor as if the cursor word doesn't exist,
+    /// so that flags typed later in the line, e.g. `once` or conflicting flags, show up in history.
+    /// Nothing is completed here, and parsing stops at a subcommand, which is out of our scope.
+    /// The args context is left untouched, since the cursor word still comes before these words.
+    /// If the cursor word is a positional, it takes its slot first, e.g. `cmd <TAB> b` puts `b` in the second one.
+    fn parse_trailing<'s>(
+        &'s self,
+        args_ctx: &ArgsContext<'s, Self::Arg>,
+        history: &mut History,
+        trailing: &[String],
+        takes_slot: bool,
+    ) {
+        let args_ctx = &mut args_ctx.clone();
+        if takes_slot && !trailing.is_empty() {
+            args_ctx.next_arg();
+        }
+        let mut args = trailing.iter().cloned().peekable();
+        while let Some(arg) = args.next() {
+            match self.parse_word(args_ctx, history, arg, &mut args, false) {
+                Ok(Parsed::Next) => (),
+                Ok(Parsed::Command(command)) => {
+                    log::info!("stop parsing trailing words at {:?}", command.id());
+                    return;
+                }
+                Ok(Parsed::FlagValue(..)) => unreachable!("all values are pushed after the cursor"),
+                Err(err) => {
+                    log::info!("error when parsing trailing words: {err:?}");
+                    return;
+                }
+            }
+        }
     }
 
     fn supplement_last(
         &self,
-        args_ctx: &mut ArgsContext,
+        runner: &Runner<Self::Ctx>,
+        args_ctx: &mut ArgsContext<Self::Arg>,
         history: &mut History,
         arg: String,
     ) -> Result<CompletionGroup> {
-        let ret: Vec<_> = match parse_flag(&arg, self.doing_external(args_ctx)) {
+        let ret: Vec<_> = match parse_flag(&arg, self.disable_flag(args_ctx)) {
             ParsedFlag::Empty | ParsedFlag::NotFlag => {
-                let cmd_slice = if args_ctx.has_seen_arg() {
+                let cmd_slice = if self.allow_subcmd(args_ctx) {
+                    log::debug!("completion for {} subcommands", self.commands().len());
+                    self.commands()
+                } else {
                     log::info!("no completion for subcmd because we've already seen some args");
                     &[]
-                } else {
-                    log::debug!("completion for {} subcommands", self.commands.len());
-                    self.commands
                 };
-                let cmd_iter = cmd_slice
+                let cmd_comps = cmd_slice
                     .iter()
-                    .map(|c| Completion::new(c.name, c.description).group("command"));
-                let arg_comp = if let Some(arg_obj) = args_ctx.next_arg() {
-                    log::debug!("completion for args {:?}", arg_obj.id);
-                    (arg_obj.comp_options)(history, &arg)
+                    .flat_map(|c| c.gen_completion().map(|comp| (c.hidden(), comp)))
+                    .collect();
+                let cmd_comps = filter_hidden(cmd_comps, &arg);
+                let index = args_ctx.value_index();
+                let (required, arg_comp) = if let Some(arg_obj) = args_ctx.next_arg() {
+                    log::debug!("completion for args {:?}", arg_obj.id());
+                    let delimiter = arg_obj.value_delimiter();
+                    let comp_options = arg_obj.comp_options();
+                    let comps = complete_value(
+                        runner,
+                        history,
+                        arg_obj.id(),
+                        comp_options,
+                        delimiter,
+                        &arg,
+                        index,
+                    )?;
+                    (arg_obj.required() && index == 0, comps)
                 } else {
                     if cmd_slice.is_empty() {
                         return Err(Error::UnexpectedArg(arg));
                     }
-                    vec![]
+                    (false, vec![])
                 };
-                cmd_iter.chain(arg_comp.into_iter()).collect()
+                if required {
+                    // A missing required positional comes before the subcommands, like required flags do.
+                    let arg_comp = arg_comp.into_iter().map(|c| mark_required(c, true));
+                    arg_comp.chain(cmd_comps).collect()
+                } else {
+                    cmd_comps.into_iter().chain(arg_comp).collect()
+                }
             }
-            ParsedFlag::DoubleDash | ParsedFlag::Long { equal: None, .. } => check_no_flag(
-                self.flags(history)
-                    .map(|f| f.gen_completion(Some(true)))
-                    .flatten()
-                    .collect(),
-            )?,
-            ParsedFlag::SingleDash => check_no_flag(
-                self.flags(history)
-                    .map(|f| f.gen_completion(None))
-                    .flatten()
-                    .collect(),
-            )?,
+            ParsedFlag::DoubleDash | ParsedFlag::Long { equal: None, .. } => {
+                check_no_flag(self.flag_completions(history, Some(true), &arg))?
+            }
+            ParsedFlag::SingleDash => check_no_flag(self.flag_completions(history, None, &arg))?,
             ParsedFlag::Long {
                 equal: Some(value),
                 body,
             } => {
                 let flag = self.find_long_flag(body, history)?;
-                let comp_options = match flag.ty {
-                    flag_type::Type::Valued(flag) => flag.comp_options,
+                let valued = match flag.ty() {
+                    flag_type::Type::Valued(flag) => flag,
                     _ => return Err(Error::BoolFlagEqualsValue(arg)),
                 };
-                comp_options(history, value)
+                valued
+                    .complete(runner, history, value, 0)?
                     .into_iter()
-                    .map(|c| c.value(|v| format!("--{body}={v}")))
+                    .map(|c| c.rewrite(|v| format!("--{body}={v}")))
                     .collect()
             }
-            ParsedFlag::Shorts => self.supplement_last_short_flags(history, &arg)?,
+            ParsedFlag::Shorts => self.supplement_last_short_flags(runner, history, &arg)?,
         };
         Ok(CompletionGroup::new(ret, arg))
     }
@@ -272,7 +821,7 @@ impl Command {
         &'b self,
         history: &mut History,
         shorts: &'a str,
-    ) -> Result<ResolvedMultiShort<'a, 'b>> {
+    ) -> Result<ResolvedMultiShort<'a, 'b, Self::Flag>> {
         let mut chars = shorts.chars().peekable();
         let mut len = 1; // ignore the first '-'
         chars.next(); // ignore the first '-'
@@ -289,7 +838,7 @@ impl Command {
                     });
                 }
                 Some('=') => {
-                    if matches!(flag.ty, flag_type::Type::Bool(_)) {
+                    if matches!(flag.ty(), flag_type::Type::Bool(_)) {
                         return Err(Error::BoolFlagEqualsValue(shorts.to_owned()));
                     };
                     len += 1;
@@ -300,25 +849,23 @@ impl Command {
                     });
                 }
                 _ => {
-                    let valued = match flag.ty {
+                    let valued = match flag.ty() {
                         flag_type::Type::Bool(inner) => {
                             inner.push(history);
                             continue;
                         }
-                        flag_type::Type::Valued(valued) => {
-                            valued
-                        }
+                        flag_type::Type::Valued(valued) => valued,
                     };
 
                     match valued.complete_with_equal {
                         CompleteWithEqual::Must => {
-                            return Err(Error::RequiresEqual(flag.id()));
+                            return Err(Error::RequiresEqual(flag.name().to_owned()));
                         }
                         CompleteWithEqual::Optional => {
                             // TODO: Maybe one day clap will tell us.
                             log::info!(
                                 "Optional flag {} doesn't have value. Push an empty string to history because we don't know its default value (clap wouldn't tell us).",
-                                flag.id(),
+                                flag.name(),
                             );
                             valued.push(history, String::new());
                         }
@@ -337,12 +884,13 @@ impl Command {
 
     fn supplement_last_short_flags(
         &self,
+        runner: &Runner<Self::Ctx>,
         history: &mut History,
         arg: &str,
     ) -> Result<Vec<Completion>> {
         let resolved = self.resolve_shorts(history, arg)?;
         let flag = resolved.last_flag;
-        let ret = match flag.ty {
+        let ret = match flag.ty() {
             flag_type::Type::Valued(inner) => {
                 let value = resolved.value.unwrap_or("");
                 let mut eq = "";
@@ -355,7 +903,7 @@ impl Command {
                             // Want: `-af=opt1`, `-af=opt2`, `-af`
                             // NOTE that we don't want `-afx`, `-afy` where x and y are other flags. That's too much.
                             more =
-                                Some(Completion::new(resolved.flag_part, &flag.get_description()));
+                                Some(Completion::new(resolved.flag_part, flag.get_description()));
                         }
                     } else {
                         // E.g. `cmd -af=xyz` or `cmd -af=`.
@@ -363,18 +911,18 @@ impl Command {
                         // Want: `-af=opt1`, `-af=opt2`
                     }
                 }
-                let iter = (inner.comp_options)(history, value)
+                let iter = inner
+                    .complete(runner, history, value, 0)?
                     .into_iter()
-                    .map(|c| c.value(|v| format!("{}{}{}", resolved.flag_part, eq, v)));
+                    .map(|c| c.rewrite(|v| format!("{}{}{}", resolved.flag_part, eq, v)));
                 more.into_iter().chain(iter).collect()
             }
             flag_type::Type::Bool(inner) => {
                 log::debug!("list short flags with history {:?}", history);
                 inner.push(history);
                 check_no_flag(
-                    self.flags(history)
-                        .map(|f| f.gen_completion(Some(false)))
-                        .flatten()
+                    self.flag_completions(history, Some(false), "")
+                        .into_iter()
                         .map(|c| {
                             c.value(|v| {
                                 let flag = &v[1..]; // skip the first '-' character
@@ -389,9 +937,8 @@ impl Command {
     }
 }
 
-#[derive(Clone, Copy)]
-struct ResolvedMultiShort<'a, 'b> {
+pub(crate) struct ResolvedMultiShort<'a, 'b, F> {
     flag_part: &'a str,
-    last_flag: &'b Flag,
+    last_flag: &'b F,
     value: Option<&'a str>,
 }
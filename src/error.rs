@@ -9,7 +9,7 @@ pub enum Error {
     BoolFlagEqualsValue(String),
     /// When a flag is supposed to have value, but got none. e.g. `git commit -m --amend`.
     /// If you're trying to supply it with a flag-like value, please do `git commit -m=--amend`.
-    FlagNoValue(&'static str),
+    FlagNoValue(String),
     /// When a flag in CLI command is not found, e.g. `git --xx=<TAB>`.
     /// Or `git --xx lo<TAB>`, though technically the `<TAB>` is not asking for that flag.
     /// `supplements` still needs to know the information about the flag to decide if the
@@ -25,7 +25,73 @@ pub enum Error {
     /// i.e. `git ''` means `git <TAB>`, which will result in all git's subcommands.
     ArgsTooShort,
     /// When a flags requires equal but there is none.
-    RequiresEqual(&'static str),
+    RequiresEqual(String),
+    /// When the cursor given to `supplement_at` doesn't point to any word.
+    /// Note that the index 0 is the program's name, which can't be completed.
+    CursorOutOfRange(usize),
+    /// When a `comp_options` fails, e.g. the binary it calls is missing.
+    /// The message is shown to the user, see `completion::print_message`.
+    Completer(String),
+    /// When `runtime::Command::comp_options` is given a path to no flag or arg with value.
+    PathNotFound(String),
+}
+impl Error {
+    /// The name of the variant, e.g. `FlagNotFound`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UnexpectedFlag => "UnexpectedFlag",
+            Error::BoolFlagEqualsValue(_) => "BoolFlagEqualsValue",
+            Error::FlagNoValue(_) => "FlagNoValue",
+            Error::FlagNotFound(_) => "FlagNotFound",
+            Error::UnexpectedArg(_) => "UnexpectedArg",
+            Error::ArgsTooShort => "ArgsTooShort",
+            Error::RequiresEqual(_) => "RequiresEqual",
+            Error::CursorOutOfRange(_) => "CursorOutOfRange",
+            Error::Completer(_) => "Completer",
+            Error::PathNotFound(_) => "PathNotFound",
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedFlag => write!(f, "no flag is expected here"),
+            Error::BoolFlagEqualsValue(arg) => {
+                write!(f, "boolean flag can't have a value: `{arg}`")
+            }
+            Error::FlagNoValue(id) => write!(f, "flag `{id}` has no value"),
+            Error::FlagNotFound(flag) => write!(f, "flag `{flag}` is not found"),
+            Error::UnexpectedArg(arg) => write!(f, "unexpected arg `{arg}`"),
+            Error::ArgsTooShort => write!(f, "there should be at least 2 args"),
+            Error::RequiresEqual(id) => write!(f, "flag `{id}` requires an equal sign"),
+            Error::CursorOutOfRange(cursor) => write!(f, "cursor {cursor} is out of range"),
+            Error::Completer(msg) => write!(f, "{msg}"),
+            Error::PathNotFound(path) => write!(f, "no flag or arg with value at `{path}`"),
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+/// The error returned by a failed `comp_options`, which becomes `Error::Completer`.
+/// Anything that can be displayed converts into it, so `?` just works:
+/// ```
+/// # use supplements::{CompResult, Completion};
+/// fn comp_options(arg: &str) -> CompResult {
+///     if arg.is_empty() {
+///         return Err("no directory is given".into());
+///     }
+///     let comps = std::fs::read_dir(arg)?
+///         .filter_map(|entry| Some(Completion::new(entry.ok()?.file_name().to_str()?, "")))
+///         .collect();
+///     Ok(comps)
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq)]
+pub struct CompError(pub String);
+impl<E: std::fmt::Display> From<E> for CompError {
+    fn from(err: E) -> Self {
+        CompError(err.to_string())
+    }
 }
 
 #[cfg(any(feature = "clap-3", feature = "clap-4"))]
@@ -56,6 +122,7 @@ pub enum GenerateError {
     UnprocessedConfigObj(Vec<Vec<String>>),
     IO(std::io::Error),
 }
+#[cfg(any(feature = "clap-3", feature = "clap-4"))]
 impl From<std::io::Error> for GenerateError {
     fn from(value: std::io::Error) -> Self {
         GenerateError::IO(value)
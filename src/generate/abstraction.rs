@@ -11,7 +11,7 @@ pub type ClapCommand<'a> = &'a mut clap::Command<'static>;
 #[cfg(feature = "clap-4")]
 pub type ClapCommand<'a> = &'a mut clap::Command;
 
-pub use clap::ArgAction;
+pub use clap::{ArgAction, ValueHint};
 
 pub(crate) struct CommandMut<'a>(pub ClapCommand<'a>);
 
@@ -43,7 +43,7 @@ impl<'a> CommandMut<'a> {
     pub fn build(&mut self) {
         self.0.build()
     }
-    pub fn to_const(self) -> Command<'a> {
+    pub fn into_const(self) -> Command<'a> {
         Command(self.0)
     }
 }
@@ -66,18 +66,50 @@ impl<'a> Command<'a> {
         }
         #[cfg(feature = "clap-4")]
         {
-            self.0.get_arguments().map(|a| Arg(a))
+            self.0.get_arguments().map(Arg)
         }
     }
     pub fn get_subcommands(&self) -> impl Iterator<Item = Command<'a>> {
-        self.0.get_subcommands().map(|c| Command(c))
+        self.0.get_subcommands().map(Command)
     }
     pub fn get_name(&self) -> &'a str {
         self.0.get_name()
     }
+    pub fn get_visible_aliases(&self) -> Vec<&'a str> {
+        self.0.get_visible_aliases().collect()
+    }
+    pub fn get_all_aliases(&self) -> Vec<&'a str> {
+        self.0.get_all_aliases().collect()
+    }
     pub fn get_about(&self) -> Option<String> {
         self.0.get_about().map(|s| s.to_string())
     }
+    pub fn is_hide_set(&self) -> bool {
+        self.0.is_hide_set()
+    }
+    pub fn get_arg_conflicts_with(&self, arg: &Arg<'_>) -> Vec<String> {
+        self.0
+            .get_arg_conflicts_with(arg.0)
+            .into_iter()
+            .map(|a| a.get_id().to_string())
+            .collect()
+    }
+    /// Groups whose args can't be used together.
+    pub fn get_exclusive_groups(&self) -> Vec<Vec<String>> {
+        #[cfg(feature = "clap-3")]
+        {
+            // clap 3 doesn't expose the content of `ArgGroup`
+            vec![]
+        }
+        #[cfg(feature = "clap-4")]
+        {
+            self.0
+                .get_groups()
+                .filter(|g| !(*g).clone().is_multiple())
+                .map(|g| g.get_args().map(|a| a.to_string()).collect())
+                .collect()
+        }
+    }
     pub fn is_allow_external_subcommands_set(&self) -> bool {
         self.0.is_allow_external_subcommands_set()
     }
@@ -99,7 +131,7 @@ impl<'a> Arg<'a> {
             // "get_num_vals" is often None even if it should take a vector,
             // so we have to make some compromise:
             // If this is the last arg, make it infinite long, otherwise make it 1
-            let default = if self.1 { std::usize::MAX } else { 1 };
+            let default = if self.1 { usize::MAX } else { 1 };
             self.0.get_num_vals().unwrap_or(default)
         }
         #[cfg(feature = "clap-4")]
@@ -110,13 +142,19 @@ impl<'a> Arg<'a> {
     pub fn get_min_num_args(&self) -> usize {
         #[cfg(feature = "clap-3")]
         {
-            if !self.takes_values() { 0 } else { 1 }
+            if !self.takes_values() {
+                return 0;
+            }
+            self.0.get_num_vals().unwrap_or(1)
         }
         #[cfg(feature = "clap-4")]
         {
             self.0.get_num_args().expect("built").min_values()
         }
     }
+    pub fn get_value_delimiter(&self) -> Option<char> {
+        self.0.get_value_delimiter()
+    }
     pub fn is_require_equals_set(&self) -> bool {
         self.0.is_require_equals_set()
     }
@@ -146,6 +184,18 @@ impl<'a> Arg<'a> {
     pub fn is_global_set(&self) -> bool {
         self.0.is_global_set()
     }
+    pub fn is_hide_set(&self) -> bool {
+        self.0.is_hide_set()
+    }
+    pub fn is_required_set(&self) -> bool {
+        self.0.is_required_set()
+    }
+    pub fn is_exclusive_set(&self) -> bool {
+        self.0.is_exclusive_set()
+    }
+    pub fn is_last_set(&self) -> bool {
+        self.0.is_last_set()
+    }
     pub fn get_short_and_visible_aliases(&self) -> Option<Vec<char>> {
         self.0.get_short_and_visible_aliases()
     }
@@ -155,6 +205,9 @@ impl<'a> Arg<'a> {
     pub fn get_action(&self) -> &ArgAction {
         self.0.get_action()
     }
+    pub fn get_value_hint(&self) -> ValueHint {
+        self.0.get_value_hint()
+    }
     pub fn get_help(&self) -> String {
         self.0.get_help().unwrap_or_default().to_string()
     }
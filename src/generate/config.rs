@@ -18,7 +18,9 @@ use std::collections::HashMap;
 #[derive(Clone)]
 pub struct Config {
     ignore: HashMap<Vec<String>, bool>,
+    requires: HashMap<Vec<String>, (Vec<String>, bool)>,
     strict: bool,
+    ctx_type: String,
 }
 
 impl Default for Config {
@@ -31,6 +33,8 @@ impl Config {
         Config {
             strict: true,
             ignore: Default::default(),
+            requires: Default::default(),
+            ctx_type: "()".to_owned(),
         }
     }
     /// Ignore a certain flag or subcommand during code-gen.
@@ -49,6 +53,33 @@ impl Config {
         self
     }
 
+    /// Only list a flag after all of `requires` are present, like clap's `requires`,
+    /// which clap doesn't expose. The flag is found by `path` just like `ignore`,
+    /// and `requires` are the ids of flags or args in the same command.
+    /// ```no_run
+    /// # use supplements::Config;
+    /// // `git log --stat` is listed only after `--graph`
+    /// let config = Config::default().requires(&["log", "stat"], &["graph"]);
+    /// ```
+    pub fn requires(mut self, path: &[&str], requires: &[&str]) -> Self {
+        let path = path.iter().map(|x| x.to_string()).collect();
+        let requires = requires.iter().map(|x| x.to_string()).collect();
+        self.requires.insert(path, (requires, false));
+        self
+    }
+
+    pub(crate) fn get_requires(&mut self, prev: &[Trace], id: &str) -> Vec<String> {
+        let mut key: Vec<_> = prev.iter().map(|t| t.cmd_id.to_string()).collect();
+        key.push(id.to_string());
+        match self.requires.get_mut(&key) {
+            Some((requires, processed)) => {
+                *processed = true;
+                requires.clone()
+            }
+            None => vec![],
+        }
+    }
+
     pub(crate) fn is_ignored(&mut self, prev: &[Trace], id: &str) -> bool {
         let mut key: Vec<_> = prev.iter().map(|t| t.cmd_id.to_string()).collect();
         key.push(id.to_string());
@@ -59,9 +90,16 @@ impl Config {
             false
         }
     }
-    pub(crate) fn unprocessed_ignore(&self) -> impl Iterator<Item = &[String]> {
-        self.ignore.iter().filter_map(|(key, processed)| {
-            if *processed {
+    /// `requires` is only processed by `generate`, so `generate_default` doesn't check it.
+    pub(crate) fn unprocessed(&self, requires: bool) -> impl Iterator<Item = &[String]> {
+        let ignore = self.ignore.iter().map(|(key, processed)| (key, *processed));
+        let requires = self
+            .requires
+            .iter()
+            .filter(move |_| requires)
+            .map(|(key, (_, processed))| (key, *processed));
+        ignore.chain(requires).filter_map(|(key, processed)| {
+            if processed {
                 None
             } else {
                 Some(key.as_slice())
@@ -69,8 +107,8 @@ impl Config {
         })
     }
 
-    pub(crate) fn check_unprocessed_config(&self) -> Result<(), GenerateError> {
-        let mut it = self.unprocessed_ignore().peekable();
+    pub(crate) fn check_unprocessed_config(&self, requires: bool) -> Result<(), GenerateError> {
+        let mut it = self.unprocessed(requires).peekable();
         if it.peek().is_none() {
             return Ok(());
         }
@@ -86,4 +124,17 @@ impl Config {
     pub fn is_strict(&self) -> bool {
         self.strict
     }
+    /// The type of the context passed to every `comp_options`, see `Command::supplement_with_ctx`.
+    /// Default to `()`. The generated code is usually included in a module, so use a full path.
+    /// ```no_run
+    /// # use supplements::Config;
+    /// let config = Config::default().ctx_type("crate::GitRepo");
+    /// ```
+    pub fn ctx_type(mut self, ty: &str) -> Self {
+        self.ctx_type = ty.to_owned();
+        self
+    }
+    pub(crate) fn get_ctx_type(&self) -> &str {
+        &self.ctx_type
+    }
 }
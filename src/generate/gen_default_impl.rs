@@ -24,10 +24,10 @@ pub fn generate_default(
 ) -> Result<(), GenerateError> {
     let mut cmd = CommandMut(cmd);
     cmd.build();
-    let cmd = cmd.to_const();
+    let cmd = cmd.into_const();
 
     generate_recur(&[], &[], &mut config, &cmd, w)?;
-    config.check_unprocessed_config()
+    config.check_unprocessed_config(false)
 }
 
 fn join_mod_prefix(prev: &[Trace]) -> String {
@@ -52,7 +52,7 @@ fn generate_recur(
     for flag in utils::flags(cmd) {
         let name = flag.get_id().to_string();
         if flag.is_global_set() {
-            if global_flags.iter().any(|f| *f == name) {
+            if global_flags.contains(&name) {
                 continue;
             } else {
                 global_flags.push(name.clone());
@@ -74,6 +74,9 @@ fn generate_recur(
     }
 
     for arg in utils::args(cmd) {
+        if !arg.get_possible_values().is_empty() {
+            continue; // const, nothing to implement
+        }
         let name = arg.get_id().to_string();
         let rust_name = gen_rust_name(NameType::ARG, &name, false);
         writeln!(w, "impl {prefix}{rust_name} for Supplements {{}}")?;
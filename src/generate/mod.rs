@@ -2,14 +2,14 @@ use crate::error::GenerateError;
 use std::borrow::Cow;
 use std::io::Write;
 
-mod abstraction;
+pub(crate) mod abstraction;
 mod config;
 mod gen_default_impl;
-mod utils;
-use abstraction::{ArgAction, ClapCommand, Command, CommandMut, PossibleValue};
+pub(crate) mod utils;
+use abstraction::{Arg, ArgAction, ClapCommand, Command, CommandMut, PossibleValue};
 pub use config::Config;
 pub use gen_default_impl::generate_default;
-use utils::{gen_rust_name, to_screaming_snake_case, to_snake_case};
+use utils::{gen_rust_name, to_snake_case};
 
 #[derive(Clone)]
 pub(crate) struct Trace {
@@ -27,11 +27,11 @@ pub(crate) struct Trace {
 ///    include!(concat!(env!("OUT_DIR"), "/definition.rs")); // File generated by `generate` function
 /// }
 /// use def::Supplements;
-/// use supplements::{Completion, History, Shell};
+/// use supplements::{CompResult, Completion, History, Shell};
 ///
 /// impl def::FlagGitDir for Supplements {} // default completion (with files)
 /// impl def::checkout::ArgFileOrCommit for Supplements {
-///     fn comp_options(_history: &History, _arg: &str) -> Vec<Completion> {
+///     fn comp_options(_ctx: &def::Ctx, _history: &History, _arg: &str, _index: usize) -> CompResult {
 ///         unimplemented!(); // your custom completion
 ///     }
 /// }
@@ -47,6 +47,9 @@ pub(crate) struct Trace {
 /// }
 /// ```
 ///full example can be found in `supplements-example/src/main.rs`
+///
+/// The default `comp_options` follows clap's `ValueHint`, e.g. `providers::dirs` for `DirPath`,
+/// `providers::executables` for `CommandName` and nothing for `Other`. Without a hint it completes files.
 pub fn generate(
     cmd: ClapCommand<'_>,
     mut config: Config,
@@ -54,11 +57,12 @@ pub fn generate(
 ) -> Result<(), GenerateError> {
     let mut cmd = CommandMut(cmd);
     cmd.build();
-    let cmd = cmd.to_const();
+    let cmd = cmd.into_const();
 
     writeln!(w, "pub struct Supplements;")?;
+    writeln!(w, "pub type Ctx = {};", config.get_ctx_type())?;
     generate_recur(&[], "", &mut config, &cmd, &[], w)?;
-    config.check_unprocessed_config()
+    config.check_unprocessed_config(true)
 }
 
 #[derive(Clone)]
@@ -104,41 +108,46 @@ where
 struct CompOptionDisplay<'a>(&'a [PossibleValue]);
 impl<'a> std::fmt::Display for CompOptionDisplay<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0.is_empty() {
-            write!(f, "None")?;
-        } else {
-            write!(f, "Some(|_, _| vec![")?;
-            let mut first = true;
-            for p in self.0.iter() {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                write!(
-                    f,
-                    "Completion::new(\"{}\", \"{}\")",
-                    p.get_name(),
-                    p.get_help().unwrap_or_default()
-                )?
+        write!(f, "|_, _, _, _| Ok(vec![")?;
+        let mut first = true;
+        for comp in utils::possible_value_comps(self.0) {
+            if first {
+                first = false;
+            } else {
+                write!(f, ", ")?;
             }
-            write!(f, "])")?;
+            write!(
+                f,
+                "Completion::new(\"{}\", \"{}\")",
+                utils::escape_help(&comp.value),
+                utils::escape_help(&comp.description)
+            )?
         }
-        Ok(())
+        write!(f, "])")
     }
 }
 
+/// How an arg is completed, see `generate_args_in_cmd`.
+enum ArgComp {
+    /// A const `Arg` completing its possible values.
+    Const(Vec<PossibleValue>),
+    /// A trait whose `comp_options` has this default body.
+    Trait(String),
+}
+
 fn generate_args_in_cmd(
     indent: &str,
     cmd: &Command<'_>,
     w: &mut impl Write,
-) -> std::io::Result<Vec<String>> {
+) -> std::io::Result<Vec<(bool, String)>> {
     let mut args_names = vec![];
 
     let ext_sub = if cmd.is_allow_external_subcommands_set() {
         log::debug!("generating external subcommand");
         let name = NameType::EXTERNAL.to_string();
-        Some((name.clone(), name, std::usize::MAX, true))
+        // The external subcommand has no default completion
+        let comp = ArgComp::Trait("Ok(vec![])".to_owned());
+        Some((name.clone(), name, usize::MAX, false, None, false, comp))
     } else {
         None
     };
@@ -148,47 +157,114 @@ fn generate_args_in_cmd(
         log::debug!("generating arg {}", name);
 
         let max_values = arg.get_max_num_args();
-        let rust_name = gen_rust_name(NameType::ARG, &name, false);
+        let last = arg.is_last_set();
+        let value_delimiter = arg.get_value_delimiter();
+        let required = arg.is_required_set();
+        let possible_values = arg.get_possible_values();
+        let is_const = !possible_values.is_empty();
+        let rust_name = gen_rust_name(NameType::ARG, &name, is_const);
+        let comp = if is_const {
+            ArgComp::Const(possible_values)
+        } else {
+            ArgComp::Trait(utils::DefaultComp::new(&arg).gen_body("_arg"))
+        };
 
-        (name, rust_name, max_values, false)
+        (
+            name,
+            rust_name,
+            max_values,
+            last,
+            value_delimiter,
+            required,
+            comp,
+        )
     });
-    let args = args.chain(ext_sub.into_iter());
+    let args = args.chain(ext_sub);
 
-    for (name, rust_name, max_values, is_external) in args {
-        let id_name = to_screaming_snake_case(&format!("id_{}_{name}", NameType::ARG));
-        let (id_type, id_enum) = if max_values == 1 {
-            ("id::SingleVal", "id::Arg::Single")
-        } else {
-            ("id::MultiVal", "id::Arg::Multi")
-        };
-        let body = if is_external {
-            "vec![]"
+    for (name, rust_name, max_values, last, value_delimiter, required, comp) in args {
+        let id_name = utils::gen_id_name(NameType::ARG, &name);
+        // Same as `utils::id_kind`, a delimited value may hold more than one value.
+        let (id_type, id_enum) = if max_values == 1 && value_delimiter.is_none() {
+            ("id::SingleVal", "id::Valued::Single")
         } else {
-            "Completion::files(_arg).collect()"
+            ("id::MultiVal", "id::Valued::Multi")
         };
-        writeln!(
-            w,
-            "\
+        match comp {
+            ArgComp::Trait(body) => {
+                writeln!(
+                    w,
+                    "\
 {indent}pub const {id_name}: {id_type} = {id_type}::new(line!(), \"{name}\");
 {indent}pub trait {rust_name} {{
-{indent}    const OBJ: Arg = Arg {{
+{indent}    const OBJ: Arg<Ctx> = Arg {{
 {indent}        id: {id_enum}({id_name}),
 {indent}        comp_options: Self::comp_options,
 {indent}        max_values: {max_values},
+{indent}        last: {last},
+{indent}        value_delimiter: {value_delimiter:?},
+{indent}        required: {required},
 {indent}    }};
 
-{indent}    fn comp_options(_history: &History, _arg: &str) -> Vec<Completion> {{
+{indent}    fn comp_options(_ctx: &Ctx, _history: &History, _arg: &str, _index: usize) -> CompResult {{
 {indent}        {body}
 {indent}    }}
 {indent}}}"
-        )?;
-
-        args_names.push(rust_name);
+                )?;
+                args_names.push((false, rust_name));
+            }
+            ArgComp::Const(possible_values) => {
+                let comp_options = CompOptionDisplay(&possible_values);
+                writeln!(
+                    w,
+                    "\
+{indent}pub const {id_name}: {id_type} = {id_type}::new(line!(), \"{name}\");
+{indent}pub const {rust_name}: Arg<Ctx> = Arg {{
+{indent}    id: {id_enum}({id_name}),
+{indent}    comp_options: {comp_options},
+{indent}    max_values: {max_values},
+{indent}    last: {last},
+{indent}    value_delimiter: {value_delimiter:?},
+{indent}    required: {required},
+{indent}}};"
+                )?;
+                args_names.push((true, rust_name));
+            }
+        }
     }
 
     Ok(args_names)
 }
 
+/// Generate `id::Any` expressions for the flags and args, e.g. those that conflict with a flag.
+/// Ignored flags and args are skipped.
+fn gen_any_ids<'a>(
+    prev: &[Trace],
+    config: &mut Config,
+    args: impl Iterator<Item = Arg<'a>>,
+    ancestor_globals: &[GlobalFlag],
+) -> Vec<String> {
+    let level = prev.len();
+    let find_global = |id: &str| ancestor_globals.iter().find(|f| f.id == id);
+    args.filter_map(|arg| {
+        let name = arg.get_id().to_string();
+        let prefix = match find_global(&name) {
+            Some(g) if g.ignored => return None,
+            Some(g) => "super::".repeat(level - g.level),
+            None if config.is_ignored(prev, &name) => return None,
+            None => String::new(),
+        };
+        let name_type = if arg.is_positional() {
+            NameType::ARG
+        } else {
+            NameType::FLAG
+        };
+        let id_kind = utils::id_kind(&arg);
+        let id_name = utils::gen_id_name(name_type, &name);
+        Some(format!("id::Any::{id_kind}({prefix}{id_name})"))
+    })
+    .collect()
+}
+
 fn generate_flags_in_cmd(
     prev: &[Trace],
     indent: &str,
@@ -198,6 +274,7 @@ fn generate_flags_in_cmd(
     w: &mut impl Write,
 ) -> Result<Vec<(bool, String)>, GenerateError> {
     let mut flag_names = vec![];
+    let ancestor_globals = global_flags.clone();
 
     for flag in utils::flags(cmd) {
         let name = flag.get_id().to_string();
@@ -232,7 +309,7 @@ fn generate_flags_in_cmd(
         let rust_name = gen_rust_name(NameType::FLAG, &name, is_const);
         if flag.is_global_set() {
             let level = prev.len();
-            if let Some(prev_flag) = global_flags.iter().find(|f| &f.id == &name) {
+            if let Some(prev_flag) = global_flags.iter().find(|f| f.id == name) {
                 log::info!("get existing global flag {name}");
                 if prev_flag.ignored {
                     continue;
@@ -259,59 +336,91 @@ fn generate_flags_in_cmd(
         let shorts = flag.get_short_and_visible_aliases().unwrap_or_default();
         let longs = flag.get_long_and_visible_aliases().unwrap_or_default();
 
-        let (once, id_type, id_enum) = match flag.get_action() {
-            ArgAction::Count => (false, "id::NoVal", "id::Flag::No"),
-            ArgAction::Append => (false, "id::MultiVal", "id::Flag::Multi"),
-            _ => {
-                let once = !flag.is_global_set();
-                if takes_values {
-                    (once, "id::SingleVal", "id::Flag::Single")
-                } else {
-                    (once, "id::NoVal", "id::Flag::No")
-                }
-            }
+        let once = match flag.get_action() {
+            ArgAction::Count | ArgAction::Append => false,
+            _ => !flag.is_global_set(),
         };
+        let id_kind = utils::id_kind(&flag);
+        let id_type = format!("id::{id_kind}Val");
+        let id_enum = (id_kind != "No").then(|| format!("id::Valued::{id_kind}"));
         let description = utils::escape_help(&flag.get_help());
+        let hidden = flag.is_hide_set();
+        let is_propagated = |id: &str| ancestor_globals.iter().any(|f| f.id == id);
+        let conflicts = utils::conflicts(cmd, &flag, is_propagated).into_iter();
+        let conflicts = gen_any_ids(prev, config, conflicts, &ancestor_globals);
+        let requires = utils::find_args(cmd, config.get_requires(prev, &name));
+        let requires = gen_any_ids(prev, config, requires, &ancestor_globals);
+        let required = flag.is_required_set();
 
         let shorts = Join(shorts.iter().map(|s| format!("'{s}'")));
         let longs = Join(longs.iter().map(|s| format!("\"{s}\"")));
-        let id_name = to_screaming_snake_case(&format!("id_{}_{name}", NameType::FLAG));
+        let conflicts = Join(conflicts.iter());
+        let requires = Join(requires.iter());
+        let id_name = utils::gen_id_name(NameType::FLAG, &name);
+        let (min_values, max_values) = (flag.get_min_num_args(), flag.get_max_num_args());
+        let mut modifiers = if takes_values && max_values > 1 {
+            format!(".with_num_values({min_values}, {max_values})")
+        } else {
+            String::new()
+        };
+        if let Some(delimiter) = flag.get_value_delimiter() {
+            modifiers += &format!(".with_value_delimiter({delimiter:?})");
+        }
+
+        let ty = |comp_options: &dyn std::fmt::Display| match &id_enum {
+            None => format!("flag_type::Type::new_bool({id_name})"),
+            Some(id_enum) => format!(
+                "flag_type::Type::new_valued({id_enum}({id_name}), {complete_with_equal}, {comp_options}){modifiers}"
+            ),
+        };
 
         if !is_const {
+            let ty = ty(&"Self::comp_options");
+            let default_comp = utils::DefaultComp::new(&flag);
+            let arg = if default_comp.uses_arg() {
+                "arg"
+            } else {
+                "_arg"
+            };
+            let body = default_comp.gen_body(arg);
             writeln!(
                 w,
                 "\
 {indent}pub const {id_name}: {id_type} = {id_type}::new(line!(), \"{name}\");
 {indent}pub trait {rust_name} {{
-{indent}    const OBJ: Flag = Flag {{
-{indent}        id: {id_enum}({id_name}),
+{indent}    const OBJ: Flag<Ctx> = Flag {{
+{indent}        ty: {ty},
 {indent}        short: &[{shorts}],
 {indent}        long: &[{longs}],
 {indent}        description: \"{description}\",
-{indent}        comp_options: Some(Self::comp_options),
 {indent}        once: {once},
-{indent}        complete_with_equal: {complete_with_equal},
+{indent}        hidden: {hidden},
+{indent}        conflicts: &[{conflicts}],
+{indent}        requires: &[{requires}],
+{indent}        required: {required},
 {indent}    }};
 
-{indent}    fn comp_options(_history: &History, arg: &str) -> Vec<Completion> {{
-{indent}        Completion::files(arg).collect()
+{indent}    fn comp_options(_ctx: &Ctx, _history: &History, {arg}: &str, _index: usize) -> CompResult {{
+{indent}        {body}
 {indent}    }}
 {indent}}}"
             )?;
         } else {
-            let comp_options = CompOptionDisplay(&possible_values);
+            let ty = ty(&CompOptionDisplay(&possible_values));
             writeln!(
                 w,
                 "\
 {indent}pub const {id_name}: {id_type} = {id_type}::new(line!(), \"{name}\");
-{indent}pub const {rust_name}: Flag = Flag {{
-{indent}    id: {id_enum}({id_name}),
+{indent}pub const {rust_name}: Flag<Ctx> = Flag {{
+{indent}    ty: {ty},
 {indent}    short: &[{shorts}],
 {indent}    long: &[{longs}],
 {indent}    description: \"{description}\",
-{indent}    comp_options: {comp_options},
 {indent}    once: {once},
-{indent}    complete_with_equal: {complete_with_equal},
+{indent}    hidden: {hidden},
+{indent}    conflicts: &[{conflicts}],
+{indent}    requires: &[{requires}],
+{indent}    required: {required},
 {indent}}};"
             )?;
         }
@@ -329,7 +438,7 @@ fn generate_subcmd_names(
     cmd: &Command<'_>,
 ) -> impl Iterator<Item = String> {
     utils::non_help_subcmd(cmd).filter_map(|c| {
-        if config.is_ignored(prev, &c.get_name()) {
+        if config.is_ignored(prev, c.get_name()) {
             None
         } else {
             Some(generate_mod_name(c.get_name()))
@@ -348,6 +457,13 @@ fn generate_recur(
     let mut global_flags = global_flags.to_vec();
     let name = cmd.get_name();
     let description = utils::escape_help(&cmd.get_about().unwrap_or_default());
+    let visible_aliases = cmd.get_visible_aliases();
+    let hidden_aliases: Vec<_> = cmd
+        .get_all_aliases()
+        .into_iter()
+        .filter(|a| !visible_aliases.contains(a))
+        .collect();
+    let hidden = cmd.is_hide_set();
     let level = prev.len();
     {
         let inner_indent = format!("    {indent}");
@@ -356,42 +472,48 @@ fn generate_recur(
         if level > 0 {
             let pre = "super::".repeat(level);
             writeln!(w, "{indent}#[allow(unused)]")?;
-            writeln!(w, "{indent}use {pre}Supplements;")?;
+            writeln!(w, "{indent}use {pre}{{Ctx, Supplements}};")?;
         }
         writeln!(w, "{indent}use supplements::*;")?;
 
-        let flags = generate_flags_in_cmd(prev, &indent, config, cmd, &mut global_flags, w)?;
-        let args = generate_args_in_cmd(&indent, cmd, w)?;
+        let flags = generate_flags_in_cmd(prev, indent, config, cmd, &mut global_flags, w)?;
+        let args = generate_args_in_cmd(indent, cmd, w)?;
         let sub_cmds: Vec<_> = generate_subcmd_names(prev, config, cmd).collect();
 
         let cmd_name = NameType::COMMAND;
 
-        let args = Join(args.iter().map(|a| format!("<Supplements as {a}>::OBJ")));
-        let flags = Join(flags.iter().map(|(is_const, f)| {
+        fn obj((is_const, name): &(bool, String)) -> Cow<'_, str> {
             if *is_const {
-                Cow::Borrowed(f)
+                Cow::Borrowed(name)
             } else {
-                Cow::Owned(format!("<Supplements as {f}>::OBJ"))
+                Cow::Owned(format!("<Supplements as {name}>::OBJ"))
             }
-        }));
+        }
+        let args = Join(args.iter().map(obj));
+        let flags = Join(flags.iter().map(obj));
         let sub_cmds = Join(sub_cmds.iter().map(|m| format!("{m}::{cmd_name}")));
+        let aliases = Join(visible_aliases.iter().map(|a| format!("\"{a}\"")));
+        let hidden_aliases = Join(hidden_aliases.iter().map(|a| format!("\"{a}\"")));
 
         writeln!(
             w,
             "\
-{indent}pub const {cmd_name}: Command = Command {{
+{indent}pub const {cmd_name}: Command<Ctx> = Command {{
 {indent}    id: id::NoVal::new(line!(), \"{name}\"),
 {indent}    name: \"{name}\",
+{indent}    aliases: &[{aliases}],
+{indent}    hidden_aliases: &[{hidden_aliases}],
 {indent}    description: \"{description}\",
 {indent}    all_flags: &[{flags}],
 {indent}    args: &[{args}],
 {indent}    commands: &[{sub_cmds}],
+{indent}    hidden: {hidden},
 {indent}}};"
         )?;
 
         for sub_cmd in utils::non_help_subcmd(cmd) {
             let cmd_id = sub_cmd.get_name().to_string();
-            if config.is_ignored(&prev, &cmd_id) {
+            if config.is_ignored(prev, &cmd_id) {
                 continue;
             }
 
@@ -399,7 +521,7 @@ fn generate_recur(
             let mut prev = prev.to_vec();
             let mod_name = generate_mod_name(&cmd_id);
             prev.push(Trace { cmd_id, mod_name });
-            generate_recur(&prev, &indent, config, &sub_cmd, &global_flags, w)?;
+            generate_recur(&prev, indent, config, &sub_cmd, &global_flags, w)?;
             writeln!(w, "{indent}}}")?;
         }
     }
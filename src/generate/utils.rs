@@ -1,21 +1,159 @@
 use super::NameType;
-use super::abstraction::{Arg, Command};
-use crate::CompleteWithEqual;
+use super::abstraction::{Arg, ArgAction, Command, PossibleValue, ValueHint};
+use crate::{CompleteWithEqual, Completion, providers};
 
-pub(super) fn flags<'a>(p: &Command<'a>) -> impl Iterator<Item = Arg<'a>> {
+pub(crate) fn flags<'a>(p: &Command<'a>) -> impl Iterator<Item = Arg<'a>> {
     p.get_arguments()
         .filter(|a| !a.is_positional() && a.get_id() != "help")
 }
 
-pub(super) fn args<'a>(p: &Command<'a>) -> impl Iterator<Item = Arg<'a>> {
+pub(crate) fn args<'a>(p: &Command<'a>) -> impl Iterator<Item = Arg<'a>> {
     p.get_arguments().filter(|a| a.is_positional())
 }
 
-pub(super) fn non_help_subcmd<'a>(p: &Command<'a>) -> impl Iterator<Item = Command<'a>> {
+pub(crate) fn non_help_subcmd<'a>(p: &Command<'a>) -> impl Iterator<Item = Command<'a>> {
     // TODO: Check if the help is default implementation
     p.get_subcommands().filter(|c| c.get_name() != "help")
 }
 
+/// All flags and args in `cmd` that can't be used together with `arg`.
+/// Conflicts of propagated global flags are skipped, they're handled where the flag is defined.
+pub(crate) fn conflicts<'a>(
+    cmd: &Command<'a>,
+    arg: &Arg<'a>,
+    is_propagated: impl Fn(&str) -> bool,
+) -> Vec<Arg<'a>> {
+    let direct_conflicts = |a: &Arg<'a>| {
+        let id = a.get_id().to_string();
+        if is_propagated(&id) {
+            vec![]
+        } else {
+            cmd.get_arg_conflicts_with(a)
+        }
+    };
+    let id = arg.get_id().to_string();
+    let groups = cmd.get_exclusive_groups();
+    let arg_conflicts = direct_conflicts(arg);
+
+    cmd.get_arguments()
+        .filter(|other| {
+            let other_id = other.get_id().to_string();
+            if other_id == id || other_id == "help" {
+                return false;
+            }
+            arg.is_exclusive_set()
+                || other.is_exclusive_set()
+                || arg_conflicts.contains(&other_id)
+                || direct_conflicts(other).contains(&id)
+                || groups
+                    .iter()
+                    .any(|g| g.contains(&id) && g.contains(&other_id))
+        })
+        .collect()
+}
+
+/// Find the flags and args in `cmd` by their ids. Ids that don't exist are skipped.
+pub(crate) fn find_args<'a>(cmd: &Command<'a>, ids: Vec<String>) -> impl Iterator<Item = Arg<'a>> {
+    cmd.get_arguments().filter(move |a| {
+        let id = a.get_id().to_string();
+        ids.contains(&id)
+    })
+}
+
+/// The kind of id for a flag or arg, i.e. `No`, `Single` or `Multi`.
+pub(crate) fn id_kind(arg: &Arg<'_>) -> &'static str {
+    if arg.is_positional() {
+        return if arg.get_max_num_args() == 1 && arg.get_value_delimiter().is_none() {
+            "Single"
+        } else {
+            "Multi"
+        };
+    }
+    match arg.get_action() {
+        ArgAction::Count => "No",
+        ArgAction::Append => "Multi",
+        _ if arg.takes_values() && arg.get_max_num_args() > 1 => "Multi",
+        _ if arg.takes_values() && arg.get_value_delimiter().is_some() => "Multi",
+        _ if arg.takes_values() => "Single",
+        _ => "No",
+    }
+}
+
+/// The completions of possible values, used by both `generate` and `runtime`.
+/// Hidden values and aliases aren't listed, but they still parse since values are never checked.
+pub(crate) fn possible_value_comps(possible_values: &[PossibleValue]) -> Vec<Completion> {
+    possible_values
+        .iter()
+        .filter(|p| !p.is_hide_set())
+        .map(|p| {
+            let help = p.get_help().map(|h| h.to_string());
+            Completion::new(p.get_name(), &help.unwrap_or_default())
+        })
+        .collect()
+}
+
+/// The default `comp_options` of a flag or arg, picked by its `ValueHint`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum DefaultComp {
+    Files,
+    Dirs,
+    ExecutablePaths,
+    Executables,
+    Users,
+    Hosts,
+    Nothing,
+}
+impl DefaultComp {
+    pub(crate) fn new(arg: &Arg<'_>) -> Self {
+        match arg.get_value_hint() {
+            ValueHint::DirPath => DefaultComp::Dirs,
+            ValueHint::ExecutablePath => DefaultComp::ExecutablePaths,
+            ValueHint::CommandName => DefaultComp::Executables,
+            ValueHint::Username => DefaultComp::Users,
+            ValueHint::Hostname => DefaultComp::Hosts,
+            // There's nothing sensible to suggest for these
+            ValueHint::Other | ValueHint::Url | ValueHint::EmailAddress => DefaultComp::Nothing,
+            _ => DefaultComp::Files,
+        }
+    }
+    /// Whether the value being typed is needed, i.e. the parameter shouldn't be named `_arg`.
+    pub(crate) fn uses_arg(self) -> bool {
+        matches!(
+            self,
+            DefaultComp::Files | DefaultComp::Dirs | DefaultComp::ExecutablePaths
+        )
+    }
+    /// The function body in generated code, where `arg` is the parameter name.
+    pub(crate) fn gen_body(self, arg: &str) -> String {
+        match self {
+            DefaultComp::Files => format!("Ok(Completion::files({arg}).collect())"),
+            DefaultComp::Dirs => format!("Ok(providers::dirs({arg}).collect())"),
+            DefaultComp::ExecutablePaths => {
+                format!("Ok(providers::Files::default().executables().complete({arg}))")
+            }
+            DefaultComp::Executables => "Ok(providers::executables().collect())".to_owned(),
+            DefaultComp::Users => "Ok(providers::users().collect())".to_owned(),
+            DefaultComp::Hosts => "Ok(providers::hosts().collect())".to_owned(),
+            DefaultComp::Nothing => "Ok(vec![])".to_owned(),
+        }
+    }
+    pub(crate) fn complete(self, arg: &str) -> Vec<Completion> {
+        match self {
+            DefaultComp::Files => Completion::files(arg).collect(),
+            DefaultComp::Dirs => providers::dirs(arg).collect(),
+            DefaultComp::ExecutablePaths => providers::Files::default().executables().complete(arg),
+            DefaultComp::Executables => providers::executables().collect(),
+            DefaultComp::Users => providers::users().collect(),
+            DefaultComp::Hosts => providers::hosts().collect(),
+            DefaultComp::Nothing => vec![],
+        }
+    }
+}
+
+pub(super) fn gen_id_name(ty: NameType, name: &str) -> String {
+    to_screaming_snake_case(&format!("id_{ty}_{name}"))
+}
+
 pub(super) fn escape_help(help: &str) -> String {
     help.replace('\n', " ").replace('"', "\\\"")
 }
@@ -43,7 +181,7 @@ pub(crate) fn to_screaming_snake_case(s: &str) -> String {
     s.replace('-', "_").to_uppercase() // TODO
 }
 
-pub(crate) fn gen_rust_name(ty: NameType, name: &str, is_const: bool) -> String {
+pub(super) fn gen_rust_name(ty: NameType, name: &str, is_const: bool) -> String {
     let mut ret = ty.to_string();
     if is_const {
         ret = ret.to_uppercase();
@@ -72,7 +210,7 @@ pub fn compute_flag_equal(
     };
     Ok(s)
 }
-fn compute_flag_equal_enum(
+pub(crate) fn compute_flag_equal_enum(
     takes_values: bool,
     min_num_args: usize,
     require_equals: bool,
@@ -0,0 +1,166 @@
+use crate::core::Completer;
+use crate::error::Error;
+use crate::{CompResult, Completion, History, Result, id};
+use std::cell::RefCell;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// A completer that can be sent to another thread, see `Guard::timeout`.
+pub(crate) type SharedComp<Ctx> =
+    Arc<dyn Fn(&Ctx, &History, &str, usize) -> CompResult + Send + Sync>;
+/// Run the completer in a new thread with a clone of the context.
+/// It's a `fn` pointer so the `Ctx: Clone + Send` bound is only needed where it's created.
+pub(crate) type SpawnFn<Ctx> =
+    fn(&Ctx, SharedComp<Ctx>, History, String, usize) -> Receiver<CompResult>;
+
+pub(crate) fn spawn<Ctx: Clone + Send + 'static>(
+    ctx: &Ctx,
+    comp: SharedComp<Ctx>,
+    history: History,
+    value: String,
+    index: usize,
+) -> Receiver<CompResult> {
+    let ctx = ctx.clone();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        // If the completer panics, the sender is dropped and the receiver sees a disconnection.
+        let _ = tx.send(comp(&ctx, &history, &value, index));
+    });
+    rx
+}
+
+/// What to complete when a `comp_options` panics or times out.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Fallback {
+    /// Complete nothing from that flag or arg. Other things like subcommands are still completed.
+    #[default]
+    Nothing,
+    /// Complete files, like the default `comp_options`.
+    Files,
+    /// The last result of the same flag or arg that succeeded under this guard, or nothing if none.
+    /// Useful when the guard lives across many completions, e.g. in an editor.
+    Cached,
+}
+
+/// Protect the completion from `comp_options` that panic or take forever,
+/// e.g. a network-backed lookup, or `git log` in a huge repo.
+/// See `Command::supplement_with_guard`.
+/// ```
+/// use std::time::Duration;
+/// use supplements::{Fallback, Guard};
+/// let guard = Guard::default()
+///     .timeout(Duration::from_millis(200))
+///     .fallback(Fallback::Files);
+/// ```
+/// Panics are always caught. Note that the panic message is still printed by the panic hook.
+#[derive(Default)]
+pub struct Guard {
+    timeout: Option<Duration>,
+    fallback: Fallback,
+    cache: RefCell<Vec<(id::Valued, Vec<Completion>)>>,
+}
+impl Guard {
+    /// Give up on a `comp_options` after `timeout`. Default to no timeout.
+    ///
+    /// With a timeout, every `comp_options` runs in a new thread, so the context is cloned into it.
+    /// A `comp_options` that times out is left running in the background until the process exits.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Default to `Fallback::Nothing`.
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    fn remember(&self, id: id::Valued, comps: &[Completion]) {
+        if self.fallback != Fallback::Cached {
+            return;
+        }
+        let mut cache = self.cache.borrow_mut();
+        match cache.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, cached)) => *cached = comps.to_vec(),
+            None => cache.push((id, comps.to_vec())),
+        }
+    }
+    fn fall_back(&self, id: id::Valued, value: &str) -> Vec<Completion> {
+        match self.fallback {
+            Fallback::Nothing => vec![],
+            Fallback::Files => Completion::files(value).collect(),
+            Fallback::Cached => {
+                let cache = self.cache.borrow();
+                let cached = cache.iter().find(|(i, _)| *i == id);
+                cached.map(|(_, comps)| comps.clone()).unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// How the parser calls `comp_options`, with the context and maybe a guard.
+pub(crate) struct Runner<'a, Ctx> {
+    ctx: &'a Ctx,
+    guard: Option<(&'a Guard, SpawnFn<Ctx>)>,
+}
+impl<'a, Ctx> Runner<'a, Ctx> {
+    pub(crate) fn new(ctx: &'a Ctx) -> Self {
+        Runner { ctx, guard: None }
+    }
+    pub(crate) fn guarded(ctx: &'a Ctx, guard: &'a Guard, spawn: SpawnFn<Ctx>) -> Self {
+        Runner {
+            ctx,
+            guard: Some((guard, spawn)),
+        }
+    }
+
+    pub(crate) fn complete(
+        &self,
+        id: id::Valued,
+        comp_options: &impl Completer<Ctx>,
+        history: &History,
+        value: &str,
+        index: usize,
+    ) -> Result<Vec<Completion>> {
+        let Some((guard, spawn)) = self.guard else {
+            return comp_options.complete(self.ctx, history, value, index);
+        };
+        let res = match guard.timeout {
+            Some(timeout) => {
+                let rx = spawn(
+                    self.ctx,
+                    comp_options.shared(),
+                    history.clone(),
+                    value.to_owned(),
+                    index,
+                );
+                match rx.recv_timeout(timeout) {
+                    Ok(res) => Ok(res.map_err(|err| Error::Completer(err.0))),
+                    Err(RecvTimeoutError::Timeout) => Err(format!("timed out after {timeout:?}")),
+                    Err(RecvTimeoutError::Disconnected) => Err("panicked".to_owned()),
+                }
+            }
+            None => catch_unwind(AssertUnwindSafe(|| {
+                comp_options.complete(self.ctx, history, value, index)
+            }))
+            .map_err(|_| "panicked".to_owned()),
+        };
+        match res {
+            Ok(res) => {
+                if let Ok(comps) = &res {
+                    guard.remember(id, comps);
+                }
+                res
+            }
+            Err(incident) => {
+                let name = id::Any::from(id).name();
+                log::warn!(
+                    "comp_options of `{name}` {incident}, fall back to {:?}",
+                    guard.fallback
+                );
+                Ok(guard.fall_back(id, value))
+            }
+        }
+    }
+}
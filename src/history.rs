@@ -1,22 +1,22 @@
 use crate::id;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HistoryUnitNoVal {
     pub id: id::NoVal,
     pub count: u32,
 }
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HistoryUnitSingleVal {
     pub id: id::SingleVal,
     pub value: String,
 }
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HistoryUnitMultiVal {
     pub id: id::MultiVal,
     pub values: Vec<String>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum HistoryUnit {
     No(HistoryUnitNoVal),
     Single(HistoryUnitSingleVal),
@@ -55,9 +55,23 @@ impl ID for id::MultiVal {
     }
 }
 
+/// For ids that are only known at runtime, e.g. from `runtime::Command::find_id`.
+impl ID for id::Any {
+    type Ret = HistoryUnit;
+    fn match_and_cast(self, h: &HistoryUnit) -> Option<&Self::Ret> {
+        let matched = match (self, h) {
+            (id::Any::No(id), HistoryUnit::No(h)) => h.id == id,
+            (id::Any::Single(id), HistoryUnit::Single(h)) => h.id == id,
+            (id::Any::Multi(id), HistoryUnit::Multi(h)) => h.id == id,
+            _ => false,
+        };
+        matched.then_some(h)
+    }
+}
+
 /// A structures that records all seen args/flags/commands, along with their value if they have some.
 /// You can search in the history by their IDs using the `find` function.
-#[derive(Default, Debug, Eq, PartialEq)]
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
 pub struct History(Vec<HistoryUnit>);
 impl History {
     pub(crate) fn push_no_val(&mut self, id: id::NoVal) {
@@ -149,6 +163,14 @@ impl History {
         None
     }
 
+    pub(crate) fn contains(&self, id: id::Any) -> bool {
+        match id {
+            id::Any::No(id) => self.find(id).is_some(),
+            id::Any::Single(id) => self.find(id).is_some(),
+            id::Any::Multi(id) => self.find(id).is_some(),
+        }
+    }
+
     #[doc(hidden)]
     pub fn from_vec(value: Vec<HistoryUnit>) -> Self {
         History(value)
@@ -40,6 +40,15 @@ pub enum Valued {
     Multi(MultiVal),
 }
 
+/// Id for any flag or arg.
+/// Used to describe the relation between flags and args, e.g. conflicts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Any {
+    No(NoVal),
+    Single(SingleVal),
+    Multi(MultiVal),
+}
+
 impl NoVal {
     pub const fn new(id: u32, ident: &'static str) -> Self {
         NoVal(id, ident)
@@ -55,3 +64,45 @@ impl MultiVal {
         MultiVal(id, ident)
     }
 }
+
+impl From<NoVal> for Any {
+    fn from(id: NoVal) -> Self {
+        Any::No(id)
+    }
+}
+impl From<SingleVal> for Any {
+    fn from(id: SingleVal) -> Self {
+        Any::Single(id)
+    }
+}
+impl From<MultiVal> for Any {
+    fn from(id: MultiVal) -> Self {
+        Any::Multi(id)
+    }
+}
+impl From<Valued> for Any {
+    fn from(id: Valued) -> Self {
+        match id {
+            Valued::Single(id) => Any::Single(id),
+            Valued::Multi(id) => Any::Multi(id),
+        }
+    }
+}
+impl Any {
+    /// The name given when creating the id, e.g. `git_dir` for the `--git-dir` flag.
+    /// Ids built by `runtime::Command` have empty names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Any::No(id) => id.1,
+            Any::Single(id) => id.1,
+            Any::Multi(id) => id.1,
+        }
+    }
+    pub(crate) fn num(&self) -> u32 {
+        match self {
+            Any::No(id) => id.0,
+            Any::Single(id) => id.0,
+            Any::Multi(id) => id.0,
+        }
+    }
+}
@@ -1,17 +1,28 @@
+pub mod cache;
 pub mod completion;
 pub mod error;
 pub mod history;
 pub mod id;
+pub mod line;
+pub mod providers;
 
+mod complete_env;
 mod core;
+mod guard;
+mod script;
+pub use complete_env::CompleteEnv;
 pub use completion::{Completion, Shell};
 pub use core::*;
+pub use guard::{Fallback, Guard};
 pub use history::History;
+pub use script::Invocation;
 
 pub(crate) mod arg_context;
 pub(crate) mod parsed_flag;
 
 pub type Result<T = ()> = std::result::Result<T, error::Error>;
+/// What `comp_options` returns. See `error::CompError`.
+pub type CompResult = std::result::Result<Vec<Completion>, error::CompError>;
 
 #[cfg(any(feature = "clap-3", feature = "clap-4"))]
 mod generate;
@@ -21,3 +32,5 @@ pub use generate::Config;
 pub use generate::generate;
 #[cfg(any(feature = "clap-3", feature = "clap-4"))]
 pub use generate::generate_default;
+#[cfg(any(feature = "clap-3", feature = "clap-4"))]
+pub mod runtime;
@@ -0,0 +1,163 @@
+/// The kind of quote that's left open at the cursor, e.g. `git checkout "my fi<TAB>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Quote {
+    Single,
+    Double,
+}
+
+/// A raw command line split into words, following POSIX shell rules for quotes, escapes and comments.
+/// Things like variables, globs or command separators are not expanded, they're kept as they are.
+/// ```
+/// use supplements::line::{Line, Quote};
+/// let line = Line::new(r#"git checkout "my fi"#, 19);
+/// assert_eq!(line.words, vec!["git", "checkout", "my fi"]);
+/// assert_eq!((line.cursor, line.offset), (2, 5));
+/// assert_eq!(line.quote, Some(Quote::Double));
+/// ```
+#[derive(Debug, Eq, PartialEq)]
+pub struct Line {
+    pub words: Vec<String>,
+    /// The index of the word where the cursor is.
+    /// If the cursor is not in any word, e.g. `git <TAB>`, an empty word is inserted there.
+    pub cursor: usize,
+    /// The byte offset of the cursor in that word, after the quotes and escapes are removed.
+    pub offset: usize,
+    /// The quote that's still open at the cursor.
+    pub quote: Option<Quote>,
+}
+
+impl Line {
+    /// The part of the cursor word before the cursor, i.e. what's being completed.
+    pub fn typed(&self) -> &str {
+        let word = self.words.get(self.cursor).map_or("", |w| w.as_str());
+        word.get(..self.offset).unwrap_or(word)
+    }
+    /// `cursor` is the byte offset in the raw line. If it's out of range, it's treated as the end of line.
+    pub fn new(raw: &str, cursor: usize) -> Self {
+        let mut words = vec![];
+        let mut word: Option<String> = None;
+        let mut quote = None;
+        let mut at_cursor = None;
+
+        let mut chars = raw.char_indices().peekable();
+        loop {
+            let next = chars.next();
+            let pos = next.map_or(raw.len(), |(i, _)| i);
+            if at_cursor.is_none() && pos >= cursor.min(raw.len()) {
+                // If the cursor is not in any word, start an empty one here.
+                let word = word.get_or_insert_with(String::new);
+                at_cursor = Some((words.len(), word.len(), quote));
+            }
+            let Some((_, ch)) = next else {
+                break;
+            };
+
+            match (quote, ch) {
+                (None, ch) if ch.is_whitespace() => words.extend(word.take()),
+                (None, '#') if word.is_none() => {
+                    log::debug!("skip comment at {pos}");
+                    while chars.next_if(|(_, ch)| *ch != '\n').is_some() {}
+                }
+                (None, '\'') => {
+                    word.get_or_insert_with(String::new);
+                    quote = Some(Quote::Single);
+                }
+                (None, '"') => {
+                    word.get_or_insert_with(String::new);
+                    quote = Some(Quote::Double);
+                }
+                (None, '\\') => match chars.next() {
+                    Some((_, '\n')) => (), // line continuation
+                    Some((_, ch)) => word.get_or_insert_with(String::new).push(ch),
+                    None => (),
+                },
+                (Some(Quote::Single), '\'') | (Some(Quote::Double), '"') => quote = None,
+                (Some(Quote::Double), '\\') => {
+                    // Inside double quotes, backslash only escapes these characters.
+                    let escaped =
+                        chars.next_if(|(_, ch)| matches!(ch, '$' | '`' | '"' | '\\' | '\n'));
+                    match escaped {
+                        Some((_, '\n')) => (),
+                        Some((_, ch)) => word.get_or_insert_with(String::new).push(ch),
+                        None => word.get_or_insert_with(String::new).push('\\'),
+                    }
+                }
+                (_, ch) => word.get_or_insert_with(String::new).push(ch),
+            }
+        }
+        words.extend(word);
+
+        let (cursor, offset, quote) = at_cursor.unwrap();
+        Line {
+            words,
+            cursor,
+            offset,
+            quote,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(line: &Line) -> Vec<&str> {
+        line.words.iter().map(|s| s.as_str()).collect()
+    }
+
+    #[test]
+    fn test_simple() {
+        let line = Line::new("git  checkout main", 100);
+        assert_eq!(words(&line), vec!["git", "checkout", "main"]);
+        assert_eq!((line.cursor, line.offset, line.quote), (2, 4, None));
+
+        let line = Line::new("git checkout ", 13);
+        assert_eq!(words(&line), vec!["git", "checkout", ""]);
+        assert_eq!((line.cursor, line.offset), (2, 0));
+
+        let line = Line::new("git  checkout", 4);
+        assert_eq!(words(&line), vec!["git", "", "checkout"]);
+        assert_eq!((line.cursor, line.offset), (1, 0));
+
+        let line = Line::new("git checkout", 6);
+        assert_eq!((line.cursor, line.offset), (1, 2));
+    }
+
+    #[test]
+    fn test_quotes() {
+        let line = Line::new(r#"git 'a "b' "c 'd" e"f"g"#, 100);
+        assert_eq!(words(&line), vec!["git", "a \"b", "c 'd", "efg"]);
+        assert_eq!(line.quote, None);
+
+        let line = Line::new("git ''", 100);
+        assert_eq!(words(&line), vec!["git", ""]);
+
+        let line = Line::new("git 'my fi", 100);
+        assert_eq!(words(&line), vec!["git", "my fi"]);
+        assert_eq!((line.cursor, line.offset), (1, 5));
+        assert_eq!(line.quote, Some(Quote::Single));
+
+        let line = Line::new(r#"git "my fi" x"#, 8);
+        assert_eq!((line.cursor, line.offset), (1, 3));
+        assert_eq!(line.quote, Some(Quote::Double));
+    }
+
+    #[test]
+    fn test_escapes() {
+        let line = Line::new(r#"git my\ file \"a\" "\$\a""#, 100);
+        assert_eq!(words(&line), vec!["git", "my file", "\"a\"", "$\\a"]);
+
+        let line = Line::new("git \\\ncheckout", 100);
+        assert_eq!(words(&line), vec!["git", "checkout"]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let line = Line::new("git a#b # comment", 100);
+        assert_eq!(words(&line), vec!["git", "a#b", ""]);
+        assert_eq!(line.cursor, 2);
+
+        let line = Line::new("git '#' # x\ncheckout", 100);
+        assert_eq!(words(&line), vec!["git", "#", "checkout"]);
+    }
+}
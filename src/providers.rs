@@ -0,0 +1,579 @@
+//! Ready-made `comp_options` bodies for values that many commands share.
+//!
+//! Each provider returns an iterator, just like `Completion::files`:
+//! ```no_run
+//! use supplements::{CompResult, History, providers};
+//!
+//! fn comp_options(_ctx: &(), _history: &History, _arg: &str, _index: usize) -> CompResult {
+//!     Ok(providers::users().collect())
+//! }
+//! ```
+//! Sources that can't be read, e.g. `/proc` on macOS, only get logged and provide nothing.
+//! Results are sorted by value with duplicates removed, except for `dirs`.
+
+use crate::Completion;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Like `Completion::files`, but only directories.
+pub fn dirs(arg: &str) -> impl Iterator<Item = Completion> {
+    Completion::files(arg).filter(|c| c.value.ends_with('/'))
+}
+
+/// When to list dotfiles, see `Files::hidden`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Hidden {
+    /// Only when the file name being typed starts with `.`, like most shells do.
+    #[default]
+    WhenTyped,
+    Always,
+    Never,
+}
+
+/// A configurable `Completion::files`.
+/// ```no_run
+/// use supplements::providers::Files;
+/// use supplements::{CompResult, History};
+///
+/// fn comp_options(_ctx: &(), _history: &History, arg: &str, _index: usize) -> CompResult {
+///     Ok(Files::default().extension("toml").max_entries(500).complete(arg))
+/// }
+/// ```
+/// - `~/`, `$VAR/` and `${VAR}/` at the start are expanded to find the files,
+///   but the inserted values keep what the user typed, e.g. `~/.config/`.
+/// - Directories, including symlinks to them, are listed with a trailing `/` so the user can keep going.
+///   They are never filtered out by `extension`, `glob` or `executables`.
+/// - Only entries starting with the file name being typed are listed, sorted by name.
+#[derive(Clone, Debug, Default)]
+pub struct Files {
+    extensions: Vec<String>,
+    globs: Vec<String>,
+    hidden: Hidden,
+    max_entries: Option<usize>,
+    executables: bool,
+}
+impl Files {
+    /// Only list files with this extension, e.g. `"toml"`.
+    /// Can be called multiple times, together with `glob`, and a file matching any of them is listed.
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extensions.push(extension.to_owned());
+        self
+    }
+    /// Only list files whose name matches the pattern, e.g. `"Cargo.*"`. Supports `*` and `?`.
+    pub fn glob(mut self, pattern: &str) -> Self {
+        self.globs.push(pattern.to_owned());
+        self
+    }
+    /// Only list executable files, e.g. for `ValueHint::ExecutablePath`.
+    /// Outside of unix, every file counts as executable.
+    pub fn executables(mut self) -> Self {
+        self.executables = true;
+        self
+    }
+    /// Default to `Hidden::WhenTyped`.
+    pub fn hidden(mut self, hidden: Hidden) -> Self {
+        self.hidden = hidden;
+        self
+    }
+    /// List at most `max` entries, so a huge directory doesn't flood the shell. Default to no limit.
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    pub fn complete(&self, arg: &str) -> Vec<Completion> {
+        // `typed_dir` is kept as is in the values, e.g. `~/src/`
+        let split = arg.rfind(std::path::is_separator).map_or(0, |i| i + 1);
+        let (typed_dir, name_prefix) = arg.split_at(split);
+        let Some(dir) = expand(typed_dir, std::env::home_dir(), |v| std::env::var_os(v)) else {
+            log::info!("can't expand {:?}", typed_dir);
+            return vec![];
+        };
+        let dir = if typed_dir.is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir
+        };
+        log::debug!("typed_dir = {:?}, dir = {:?}", typed_dir, dir);
+        // A kept `$VAR` is for the shell to expand, so it must not be escaped
+        let typed_prefix = match typed_dir.starts_with('$') {
+            true => typed_dir.find(std::path::is_separator).unwrap_or(0),
+            false => 0,
+        };
+        let show_hidden = match self.hidden {
+            Hidden::WhenTyped => name_prefix.starts_with('.'),
+            Hidden::Always => true,
+            Hidden::Never => false,
+        };
+
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => Some(entries),
+            Err(err) => {
+                log::warn!("error reading {:?}: {:?}", dir, err);
+                None
+            }
+        };
+        let mut found = vec![];
+        for entry in entries.into_iter().flatten().flatten() {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !name.starts_with(name_prefix) || (name.starts_with('.') && !show_hidden) {
+                continue;
+            }
+            // `DirEntry::file_type` doesn't follow symlinks
+            let is_dir = match entry.file_type() {
+                Ok(ty) if ty.is_symlink() => entry.path().is_dir(),
+                Ok(ty) => ty.is_dir(),
+                Err(_) => false,
+            };
+            if !is_dir && !self.matches(&name) {
+                continue;
+            }
+            if !is_dir && self.executables && !is_executable(&entry.path()) {
+                continue;
+            }
+            found.push((name, is_dir));
+        }
+        found.sort();
+        if let Some(max) = self.max_entries
+            && found.len() > max
+        {
+            log::info!("{} entries in {:?}, only list {}", found.len(), dir, max);
+            found.truncate(max);
+        }
+
+        found
+            .into_iter()
+            .map(|(name, is_dir)| {
+                let value = format!("{typed_dir}{name}");
+                let comp = if is_dir {
+                    Completion::new(&format!("{value}/"), "").no_space()
+                } else {
+                    Completion::new(&value, "")
+                };
+                comp.typed_prefix(typed_prefix)
+            })
+            .collect()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        if self.extensions.is_empty() && self.globs.is_empty() {
+            return true;
+        }
+        let extension = Path::new(name).extension().and_then(|e| e.to_str());
+        let by_extension = extension.is_some_and(|e| self.extensions.iter().any(|x| x == e));
+        by_extension
+            || self
+                .globs
+                .iter()
+                .any(|g| glob_match(g.as_bytes(), name.as_bytes()))
+    }
+}
+
+/// Expand `~`, `$VAR` or `${VAR}` at the start of `typed`. `None` if it's not set.
+fn expand(
+    typed: &str,
+    home: Option<PathBuf>,
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    let Some(i) = typed.find(std::path::is_separator) else {
+        return Some(PathBuf::from(typed));
+    };
+    let (head, rest) = typed.split_at(i);
+    let base = if head == "~" {
+        home?
+    } else if let Some(name) = head.strip_prefix('$') {
+        let name = name.strip_prefix('{').and_then(|n| n.strip_suffix('}'));
+        PathBuf::from(var(name.unwrap_or(&head[1..]))?)
+    } else {
+        return Some(PathBuf::from(typed));
+    };
+    let mut path = base.into_os_string();
+    path.push(rest);
+    Some(path.into())
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', p)), _) => {
+            glob_match(p, name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some((b'?', p)), Some((_, n))) => glob_match(p, n),
+        (Some((c, p)), Some((d, n))) => c == d && glob_match(p, n),
+        (Some(_), None) => false,
+    }
+}
+
+/// Executables in the directories of `$PATH`. The description is the directory it's found in.
+pub fn executables() -> impl Iterator<Item = Completion> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut found = BTreeMap::new();
+    for dir in std::env::split_paths(&path) {
+        for name in read_dir(&dir) {
+            if is_executable(&dir.join(&name)) {
+                // The first one in `$PATH` is the one that runs
+                found
+                    .entry(name)
+                    .or_insert_with(|| dir.to_string_lossy().into_owned());
+            }
+        }
+    }
+    into_completions(found)
+}
+
+/// Names of the environment variables, without the leading `$`.
+pub fn env_vars() -> impl Iterator<Item = Completion> {
+    let found = std::env::vars_os()
+        .map(|(name, _)| (name.to_string_lossy().into_owned(), String::new()))
+        .collect();
+    into_completions(found)
+}
+
+/// Local users from `/etc/passwd`. The description is the full name, if any.
+pub fn users() -> impl Iterator<Item = Completion> {
+    into_completions(parse_passwd(&read_file("/etc/passwd")))
+}
+
+/// Local groups from `/etc/group`.
+pub fn groups() -> impl Iterator<Item = Completion> {
+    into_completions(parse_group(&read_file("/etc/group")))
+}
+
+/// Hostnames from `/etc/hosts` and `~/.ssh/config`.
+/// The description is the address, or `ssh` for hosts that only exist in the ssh config.
+/// Wildcard patterns like `Host *.internal` are skipped.
+pub fn hosts() -> impl Iterator<Item = Completion> {
+    let mut found = parse_hosts(&read_file("/etc/hosts"));
+    if let Some(home) = std::env::home_dir() {
+        let ssh_config = read_file(home.join(".ssh").join("config"));
+        for host in parse_ssh_config(&ssh_config) {
+            found.entry(host).or_insert_with(|| "ssh".to_owned());
+        }
+    }
+    into_completions(found)
+}
+
+/// PIDs of running processes from `/proc`. The description is the process name.
+pub fn pids() -> impl Iterator<Item = Completion> {
+    // Keyed by number, so they're sorted by number rather than by string
+    processes(Path::new("/proc"))
+        .into_iter()
+        .map(|(pid, name)| Completion::new(&pid.to_string(), &name))
+}
+
+/// Names of running processes from `/proc`, e.g. for `pkill` or `killall`.
+pub fn process_names() -> impl Iterator<Item = Completion> {
+    let found = processes(Path::new("/proc"))
+        .into_values()
+        .map(|name| (name, String::new()))
+        .collect();
+    into_completions(found)
+}
+
+/// Network interfaces from `/sys/class/net`, e.g. `lo` and `eth0`.
+pub fn interfaces() -> impl Iterator<Item = Completion> {
+    let found = read_dir(Path::new("/sys/class/net"))
+        .map(|name| (name, String::new()))
+        .collect();
+    into_completions(found)
+}
+
+fn into_completions(found: BTreeMap<String, String>) -> impl Iterator<Item = Completion> {
+    found
+        .into_iter()
+        .map(|(value, desc)| Completion::new(&value, &desc))
+}
+
+fn read_file(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            log::info!("error reading {:?}: {:?}", path, err);
+            String::new()
+        }
+    }
+}
+
+/// Yields file names. The ones that are not UTF-8 are skipped.
+fn read_dir(dir: &Path) -> impl Iterator<Item = String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => Some(entries),
+        Err(err) => {
+            log::info!("error reading {:?}: {:?}", dir, err);
+            None
+        }
+    };
+    entries
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    // Follow symlinks, since that's how a lot of executables get installed
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn parse_passwd(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next().filter(|n| !n.is_empty())?;
+            // GECOS is the 5th field, where the full name comes first
+            let gecos = fields.nth(3).unwrap_or_default();
+            let full_name = gecos.split(',').next().unwrap_or_default();
+            Some((name.to_owned(), full_name.to_owned()))
+        })
+        .collect()
+}
+
+fn parse_group(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let name = line.split(':').next().filter(|n| !n.is_empty())?;
+            Some((name.to_owned(), String::new()))
+        })
+        .collect()
+}
+
+fn parse_hosts(content: &str) -> BTreeMap<String, String> {
+    let mut found = BTreeMap::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(addr) = words.next() else {
+            continue;
+        };
+        for name in words {
+            found
+                .entry(name.to_owned())
+                .or_insert_with(|| addr.to_owned());
+        }
+    }
+    found
+}
+
+fn parse_ssh_config(content: &str) -> Vec<String> {
+    let mut found = vec![];
+    for line in content.lines() {
+        let line = line.trim_start();
+        // Both `Host a b` and `Host=a b` are allowed, and keywords are case-insensitive
+        let Some((keyword, rest)) = line.split_once(|c: char| c.is_whitespace() || c == '=') else {
+            continue;
+        };
+        if !keyword.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        let rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+        let rest = rest.split('#').next().unwrap_or_default();
+        let hosts = rest.split_whitespace().map(|h| h.trim_matches('"'));
+        let hosts = hosts.filter(|h| !h.is_empty() && !h.contains(['*', '?', '!']));
+        found.extend(hosts.map(str::to_owned));
+    }
+    found
+}
+
+fn processes(proc_dir: &Path) -> BTreeMap<u32, String> {
+    read_dir(proc_dir)
+        .filter_map(|name| {
+            let pid = name.parse().ok()?;
+            // The process may have exited after `read_dir`
+            let comm = std::fs::read_to_string(proc_dir.join(&name).join("comm")).ok()?;
+            Some((pid, comm.trim_end().to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(comps: impl Iterator<Item = Completion>) -> Vec<String> {
+        comps.map(|c| c.value).collect()
+    }
+
+    #[test]
+    fn test_parse_passwd_and_group() {
+        let passwd = "# comment\nroot:x:0:0:root:/root:/bin/bash\n\
+            alice:x:1000:1000:Alice Liddell,,,:/home/alice:/bin/zsh\n\
+            nobody:x:65534:65534::/nonexistent:/usr/sbin/nologin\n";
+        let passwd = parse_passwd(passwd);
+        assert_eq!(
+            passwd.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("alice".to_owned(), "Alice Liddell".to_owned()),
+                ("nobody".to_owned(), "".to_owned()),
+                ("root".to_owned(), "root".to_owned()),
+            ]
+        );
+        let group = parse_group("root:x:0:\nwheel:x:10:alice\n\n");
+        assert_eq!(group.into_keys().collect::<Vec<_>>(), vec!["root", "wheel"]);
+    }
+
+    #[test]
+    fn test_parse_hosts_and_ssh_config() {
+        let hosts = "127.0.0.1 localhost\n\n# 10.0.0.1 commented\n\
+            ::1\tlocalhost ip6-localhost # trailing comment\n10.0.0.2 db db.internal\n";
+        let hosts = parse_hosts(hosts);
+        assert_eq!(
+            hosts.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("db".to_owned(), "10.0.0.2".to_owned()),
+                ("db.internal".to_owned(), "10.0.0.2".to_owned()),
+                ("ip6-localhost".to_owned(), "::1".to_owned()),
+                ("localhost".to_owned(), "127.0.0.1".to_owned()),
+            ]
+        );
+
+        let config = "Host github gitlab\n  HostName github.com\n\
+            host=bastion\nHost *.internal !secret\n  Hostname x\nHOST \"quoted\" # comment\n";
+        assert_eq!(
+            parse_ssh_config(config),
+            vec!["github", "gitlab", "bastion", "quoted"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_sources() {
+        let me = std::process::id().to_string();
+        assert!(pids().any(|c| c.value == me));
+        assert!(process_names().next().is_some());
+        assert!(values(interfaces()).contains(&"lo".to_owned()));
+        assert!(values(users()).contains(&"root".to_owned()));
+        assert!(values(groups()).contains(&"root".to_owned()));
+    }
+
+    #[test]
+    fn test_executables_and_env_vars() {
+        assert!(values(env_vars()).contains(&"PATH".to_owned()));
+        #[cfg(unix)]
+        assert!(values(executables()).contains(&"sh".to_owned()));
+    }
+
+    #[test]
+    fn test_expand() {
+        let home = Some(PathBuf::from("/home/me"));
+        let var = |name: &str| (name == "CFG").then(|| OsString::from("/etc/cfg"));
+        let expand = |typed| expand(typed, home.clone(), var);
+        assert_eq!(expand(""), Some(PathBuf::from("")));
+        assert_eq!(expand("src/"), Some(PathBuf::from("src/")));
+        assert_eq!(expand("~/src/"), Some(PathBuf::from("/home/me/src/")));
+        assert_eq!(expand("$CFG/a/"), Some(PathBuf::from("/etc/cfg/a/")));
+        assert_eq!(expand("${CFG}/"), Some(PathBuf::from("/etc/cfg/")));
+        assert_eq!(expand("$NOPE/"), None);
+        assert_eq!(expand("a/~/"), Some(PathBuf::from("a/~/")));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"Cargo.*", b"Cargo.toml"));
+        assert!(glob_match(b"*.t?ml", b"a.toml"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"Cargo.*", b"cargo.toml"));
+        assert!(!glob_match(b"?", b""));
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("supplements-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["a.toml", "b.rs", ".hidden.toml", "Cargo.lock"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("sub"), dir.join("link")).unwrap();
+
+        let typed = format!("{}/", dir.to_string_lossy());
+        let complete = |files: Files, name: &str| -> Vec<(String, bool)> {
+            let comps = files.complete(&format!("{typed}{name}"));
+            let comps = comps.into_iter();
+            comps
+                .map(|c| (c.value[typed.len()..].to_owned(), c.no_space))
+                .collect()
+        };
+        let expected = |names: &[&str]| -> Vec<(String, bool)> {
+            let names = names.iter().filter(|n| cfg!(unix) || **n != "link/");
+            names.map(|n| (n.to_string(), n.ends_with('/'))).collect()
+        };
+
+        let toml = Files::default().extension("toml");
+        assert_eq!(
+            complete(toml.clone(), ""),
+            expected(&["a.toml", "link/", "sub/"])
+        );
+        assert_eq!(complete(toml.clone(), "."), expected(&[".hidden.toml"]));
+        assert_eq!(complete(toml.clone(), "s"), expected(&["sub/"]));
+        let always = toml.clone().hidden(Hidden::Always);
+        assert_eq!(
+            complete(always, ""),
+            expected(&[".hidden.toml", "a.toml", "link/", "sub/"])
+        );
+        assert_eq!(complete(toml.hidden(Hidden::Never), "."), expected(&[]));
+
+        let cargo = Files::default().glob("Cargo.*").extension("rs");
+        assert_eq!(
+            complete(cargo, ""),
+            expected(&["Cargo.lock", "b.rs", "link/", "sub/"])
+        );
+        assert_eq!(
+            complete(Files::default().max_entries(2), ""),
+            expected(&["Cargo.lock", "a.toml"])
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = dir.join("run.sh");
+            std::fs::write(&script, "").unwrap();
+            let perm = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(&script, perm).unwrap();
+            assert_eq!(
+                complete(Files::default().executables(), ""),
+                expected(&["link/", "run.sh", "sub/"])
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_keep_var() {
+        if std::env::var_os("HOME").is_none() {
+            return;
+        }
+        let comps = Files::default().complete("$HOME/");
+        let grp = crate::completion::CompletionGroup::new(comps, "$HOME/".to_owned());
+        let mut out = vec![];
+        grp.print(crate::Shell::Bash, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.lines()
+                .all(|l| l.starts_with("$HOME/") || l == "\tnospace"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_dirs() {
+        let dirs = values(dirs(""));
+        assert!(dirs.contains(&"src/".to_owned()));
+        assert!(dirs.iter().all(|d| d.ends_with('/')));
+    }
+}
@@ -0,0 +1,592 @@
+//! Command definition built at runtime from a `clap` command, without any code-gen.
+//!
+//! It's handy for tools that don't want a build step, at the cost of doing some work on every completion.
+//! Custom completion logic can still be attached to any flag or arg by its path.
+//! ```no_run
+//! # #[cfg(feature = "clap-3")]
+//! # use clap3 as clap;
+//! # #[cfg(feature = "clap-4")]
+//! # use clap4 as clap;
+//! use supplements::{Completion, runtime};
+//!
+//! let mut cmd = clap::Command::new("git");
+//! let cmd = runtime::Command::from_clap(&mut cmd)
+//!     .comp_options(&["log", "commit"], |_ctx, _history, _arg, _index| {
+//!         Ok(vec![Completion::new("HEAD", "")])
+//!     })
+//!     .unwrap();
+//! let comps = cmd.supplement(std::env::args().skip(1)).unwrap();
+//! ```
+
+use crate::completion::CompletionGroup;
+use crate::core::{ArgDef, CommandDef, Completer, FlagDef};
+use crate::error::Error;
+use crate::flag_type::{Bool, Type, Valued};
+use crate::generate::abstraction::{self as clap_abs, ArgAction, ClapCommand, CommandMut};
+use crate::generate::utils;
+use crate::guard::{Guard, Runner, SharedComp, spawn};
+use crate::{CompResult, CompleteWithEqual, Completion, History, Result, id};
+use std::sync::Arc;
+
+type CompFn<Ctx> = SharedComp<Ctx>;
+
+impl<Ctx> Completer<Ctx> for CompFn<Ctx> {
+    fn complete(
+        &self,
+        ctx: &Ctx,
+        history: &History,
+        value: &str,
+        index: usize,
+    ) -> Result<Vec<Completion>> {
+        self(ctx, history, value, index).map_err(|err| Error::Completer(err.0))
+    }
+    fn shared(&self) -> SharedComp<Ctx> {
+        self.clone()
+    }
+}
+
+/// The runtime version of `supplements::Flag`.
+pub struct Flag<Ctx = ()> {
+    name: String,
+    ty: Type<CompFn<Ctx>>,
+    short: Vec<char>,
+    long: Vec<String>,
+    description: String,
+    once: bool,
+    hidden: bool,
+    conflicts: Vec<id::Any>,
+    requires: Vec<id::Any>,
+    required: bool,
+}
+
+// Derived `Clone` would require `Ctx: Clone`, which is not needed for the shared completers.
+impl<Ctx> Clone for Flag<Ctx> {
+    fn clone(&self) -> Self {
+        Flag {
+            name: self.name.clone(),
+            ty: self.ty.clone(),
+            short: self.short.clone(),
+            long: self.long.clone(),
+            description: self.description.clone(),
+            conflicts: self.conflicts.clone(),
+            requires: self.requires.clone(),
+            ..*self
+        }
+    }
+}
+
+/// The runtime version of `supplements::Arg`.
+pub struct Arg<Ctx = ()> {
+    name: String,
+    id: id::Valued,
+    comp_options: CompFn<Ctx>,
+    max_values: usize,
+    last: bool,
+    value_delimiter: Option<char>,
+    required: bool,
+}
+
+/// The runtime version of `supplements::Command`.
+/// Create it with `Command::from_clap`, and call `supplement` just like the const one.
+pub struct Command<Ctx = ()> {
+    id: id::NoVal,
+    name: String,
+    aliases: Vec<String>,
+    hidden_aliases: Vec<String>,
+    description: String,
+    all_flags: Vec<Flag<Ctx>>,
+    args: Vec<Arg<Ctx>>,
+    commands: Vec<Command<Ctx>>,
+    hidden: bool,
+}
+
+impl<Ctx: 'static> Command<Ctx> {
+    /// Build the command tree with the same rules as `generate`.
+    /// Flags with possible values complete them, and everything else completes by its `ValueHint`,
+    /// e.g. directories for `DirPath` and nothing for `Other`, or files if there's no hint.
+    /// That lasts until something else is attached by `comp_options`.
+    ///
+    /// The names are kept in the command tree, so the ids themselves have empty names.
+    /// Use `find_id` to look them up.
+    pub fn from_clap(cmd: ClapCommand<'_>) -> Self {
+        let mut cmd = CommandMut(cmd);
+        cmd.build();
+        let cmd = cmd.into_const();
+        Builder::default().build(&cmd, &[])
+    }
+
+    /// Replace how the values of a flag or arg are completed.
+    /// The `path` is the names of subcommands followed by the id of the flag or arg,
+    /// e.g. `&["log", "commit"]` for `git log <commit>`, or `&["git-dir"]` for the global `--git-dir` flag.
+    ///
+    /// Returns `Error::PathNotFound` if there is no such flag or arg.
+    /// Note that boolean flags have nothing to complete.
+    pub fn comp_options(
+        mut self,
+        path: &[&str],
+        comp_options: impl Fn(&Ctx, &History, &str, usize) -> CompResult + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let comp_options: CompFn<Ctx> = Arc::new(comp_options);
+        let (id, cmd) = match self.find_mut(path) {
+            Some((id::Any::No(_), _)) | None => return Err(Error::PathNotFound(path.join(" "))),
+            Some(found) => found,
+        };
+        if let Some(arg) = cmd.args.iter_mut().find(|a| id::Any::from(a.id) == id) {
+            arg.comp_options = comp_options;
+        } else {
+            cmd.update_flag(id, &mut |flag| {
+                if let Type::Valued(valued) = &mut flag.ty {
+                    valued.comp_options = comp_options.clone();
+                }
+            });
+        }
+        Ok(self)
+    }
+
+    /// Only list a flag after all of `requires` are present, like clap's `requires`,
+    /// which clap doesn't expose. See `comp_options` for the `path`,
+    /// and `requires` are the ids of flags or args in the same command.
+    ///
+    /// Returns `Error::PathNotFound` if the flag or any of `requires` doesn't exist.
+    pub fn requires(mut self, path: &[&str], requires: &[&str]) -> Result<Self> {
+        let cmd_path = path.split_last().map_or(&[][..], |(_, cmd_path)| cmd_path);
+        let requires = requires
+            .iter()
+            .map(|name| {
+                let req_path: Vec<_> = cmd_path.iter().chain([name]).copied().collect();
+                self.find_id(&req_path)
+                    .ok_or_else(|| Error::PathNotFound(req_path.join(" ")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (id, cmd) = match self.find_mut(path) {
+            Some((id, cmd)) if !cmd.args.iter().any(|a| id::Any::from(a.id) == id) => (id, cmd),
+            _ => return Err(Error::PathNotFound(path.join(" "))),
+        };
+        cmd.update_flag(id, &mut |flag| flag.requires = requires.clone());
+        Ok(self)
+    }
+
+    /// Find the id of a flag or arg, to search for its value in `History`. See `comp_options` for the `path`.
+    pub fn find_id(&self, path: &[&str]) -> Option<id::Any> {
+        let (name, cmd_path) = path.split_last()?;
+        let mut cmd = self;
+        for sub in cmd_path {
+            cmd = cmd.commands.iter().find(|c| c.name == *sub)?;
+        }
+        let flags = cmd.all_flags.iter().map(|f| (&f.name, f.any_id()));
+        let args = cmd.args.iter().map(|a| (&a.name, id::Any::from(a.id)));
+        let (_, id) = flags.chain(args).find(|(n, _)| n == name)?;
+        Some(id)
+    }
+
+    fn find_mut(&mut self, path: &[&str]) -> Option<(id::Any, &mut Command<Ctx>)> {
+        let id = self.find_id(path)?;
+        let mut cmd = self;
+        for sub in &path[..path.len() - 1] {
+            cmd = cmd.commands.iter_mut().find(|c| c.name == *sub)?;
+        }
+        Some((id, cmd))
+    }
+
+    /// A global flag has a copy in every subcommand, so all of them are updated.
+    fn update_flag(&mut self, id: id::Any, f: &mut impl FnMut(&mut Flag<Ctx>)) {
+        for flag in self.all_flags.iter_mut() {
+            if flag.any_id() == id {
+                f(flag);
+            }
+        }
+        for cmd in self.commands.iter_mut() {
+            cmd.update_flag(id, f);
+        }
+    }
+
+    /// Like `supplements::Command::supplement_with_ctx`.
+    pub fn supplement_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_with_trailing(&Runner::new(ctx), history, args, &[])
+    }
+
+    /// Like `supplements::Command::supplement_at_with_ctx`.
+    pub fn supplement_at_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_at_with_runner(&Runner::new(ctx), history, args, cursor, offset)
+    }
+
+    /// Like `supplements::Command::supplement_line_with_ctx`.
+    pub fn supplement_line_with_ctx(
+        &self,
+        ctx: &Ctx,
+        history: &mut History,
+        line: &str,
+        cursor: usize,
+    ) -> Result<CompletionGroup> {
+        self.supplement_line_with_runner(&Runner::new(ctx), history, line, cursor)
+    }
+
+    /// Like `supplements::Command::supplement_with_guard`.
+    pub fn supplement_with_guard(
+        &self,
+        guard: &Guard,
+        ctx: &Ctx,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup>
+    where
+        Ctx: Clone + Send,
+    {
+        let runner = Runner::guarded(ctx, guard, spawn);
+        self.supplement_with_trailing(&runner, history, args, &[])
+    }
+
+    /// Like `supplements::Command::supplement_line_with_guard`.
+    pub fn supplement_line_with_guard(
+        &self,
+        guard: &Guard,
+        ctx: &Ctx,
+        history: &mut History,
+        line: &str,
+        cursor: usize,
+    ) -> Result<CompletionGroup>
+    where
+        Ctx: Clone + Send,
+    {
+        let runner = Runner::guarded(ctx, guard, spawn);
+        self.supplement_line_with_runner(&runner, history, line, cursor)
+    }
+}
+
+impl Command {
+    /// See `supplements::Command::supplement`.
+    pub fn supplement(&self, args: impl Iterator<Item = String>) -> Result<CompletionGroup> {
+        let mut history = History::default();
+        self.supplement_with_history(&mut history, args)
+    }
+
+    pub fn supplement_with_history(
+        &self,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_with_ctx(&(), history, args)
+    }
+
+    /// See `supplements::Command::supplement_at`.
+    pub fn supplement_at(
+        &self,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        let mut history = History::default();
+        self.supplement_at_with_history(&mut history, args, cursor, offset)
+    }
+
+    pub fn supplement_at_with_history(
+        &self,
+        history: &mut History,
+        args: impl Iterator<Item = String>,
+        cursor: usize,
+        offset: Option<usize>,
+    ) -> Result<CompletionGroup> {
+        self.supplement_at_with_ctx(&(), history, args, cursor, offset)
+    }
+
+    /// See `supplements::Command::supplement_line`.
+    pub fn supplement_line(&self, line: &str, cursor: usize) -> Result<CompletionGroup> {
+        let mut history = History::default();
+        self.supplement_line_with_ctx(&(), &mut history, line, cursor)
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    next_id: u32,
+}
+
+impl Builder {
+    /// Ids only need to be unique. Their names are left empty, since the `'static` names
+    /// `id` requires are not available at runtime. The names are stored in the tree instead.
+    fn ident(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn any_id(&mut self, arg: &clap_abs::Arg<'_>) -> id::Any {
+        let id = self.ident();
+        match utils::id_kind(arg) {
+            "No" => id::Any::No(id::NoVal::new(id, "")),
+            "Single" => id::Any::Single(id::SingleVal::new(id, "")),
+            _ => id::Any::Multi(id::MultiVal::new(id, "")),
+        }
+    }
+
+    /// `globals` are the global flags defined in the ancestors, which are shared with this command.
+    fn build<Ctx: 'static>(
+        &mut self,
+        cmd: &clap_abs::Command<'_>,
+        globals: &[Flag<Ctx>],
+    ) -> Command<Ctx> {
+        let find_global = |name: &str| globals.iter().find(|f| f.name == name);
+
+        // All ids must be known before building the flags, since flags refer to each other, e.g. `conflicts`.
+        let ids: Vec<_> = cmd
+            .get_arguments()
+            .filter(|a| a.get_id() != "help")
+            .map(|a| {
+                let name = a.get_id().to_string();
+                let id = match find_global(&name) {
+                    Some(global) => global.any_id(),
+                    None => self.any_id(&a),
+                };
+                (name, id)
+            })
+            .collect();
+        let find_ids = |args: Vec<clap_abs::Arg<'_>>| -> Vec<id::Any> {
+            args.iter()
+                .filter_map(|a| ids.iter().find(|(name, _)| a.get_id() == name.as_str()))
+                .map(|(_, id)| *id)
+                .collect()
+        };
+        let find_id = |name: &str| ids.iter().find(|(n, _)| n == name).map(|(_, id)| *id);
+
+        let mut all_flags = vec![];
+        let mut sub_globals = globals.to_vec();
+        for flag in utils::flags(cmd) {
+            let name = flag.get_id().to_string();
+            if let Some(global) = find_global(&name) {
+                log::info!("get existing global flag {name}");
+                all_flags.push(global.clone());
+                continue;
+            }
+            log::debug!("building flag {name}");
+
+            let Some(id) = find_id(&name) else {
+                log::warn!("no id for flag {name}, skip it");
+                continue;
+            };
+            let ty = match id {
+                id::Any::No(id) => Type::Bool(Bool { id }),
+                id::Any::Single(id) => self.valued(&flag, id::Valued::Single(id)),
+                id::Any::Multi(id) => self.valued(&flag, id::Valued::Multi(id)),
+            };
+            let once = match flag.get_action() {
+                ArgAction::Count | ArgAction::Append => false,
+                _ => !flag.is_global_set(),
+            };
+            let is_propagated = |id: &str| find_global(id).is_some();
+            let flag_obj = Flag {
+                name: name.clone(),
+                ty,
+                short: flag.get_short_and_visible_aliases().unwrap_or_default(),
+                long: flag
+                    .get_long_and_visible_aliases()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                description: flag.get_help(),
+                once,
+                hidden: flag.is_hide_set(),
+                conflicts: find_ids(utils::conflicts(cmd, &flag, is_propagated)),
+                requires: vec![],
+                required: flag.is_required_set(),
+            };
+            if flag.is_global_set() {
+                log::info!("get new global flag {name}");
+                sub_globals.push(flag_obj.clone());
+            }
+            all_flags.push(flag_obj);
+        }
+
+        let mut args: Vec<_> = utils::args(cmd)
+            .filter_map(|arg| {
+                let name = arg.get_id().to_string();
+                let id = match find_id(&name) {
+                    Some(id::Any::Single(id)) => id::Valued::Single(id),
+                    Some(id::Any::Multi(id)) => id::Valued::Multi(id),
+                    _ => {
+                        log::warn!("no id with value for arg {name}, skip it");
+                        return None;
+                    }
+                };
+                let comp_options = comp_options(&arg);
+                Some(Arg {
+                    name,
+                    id,
+                    comp_options,
+                    max_values: arg.get_max_num_args(),
+                    last: arg.is_last_set(),
+                    value_delimiter: arg.get_value_delimiter(),
+                    required: arg.is_required_set(),
+                })
+            })
+            .collect();
+        if cmd.is_allow_external_subcommands_set() {
+            log::debug!("building external subcommand");
+            let id = self.ident();
+            args.push(Arg {
+                name: "External".to_owned(),
+                id: id::Valued::Multi(id::MultiVal::new(id, "")),
+                comp_options: Arc::new(|_, _, _, _| Ok(vec![])),
+                max_values: usize::MAX,
+                last: false,
+                value_delimiter: None,
+                required: false,
+            });
+        }
+
+        let visible_aliases = cmd.get_visible_aliases();
+        let hidden_aliases = cmd
+            .get_all_aliases()
+            .into_iter()
+            .filter(|a| !visible_aliases.contains(a))
+            .map(String::from)
+            .collect();
+        let commands = utils::non_help_subcmd(cmd)
+            .map(|sub| self.build(&sub, &sub_globals))
+            .collect();
+        Command {
+            id: id::NoVal::new(self.ident(), ""),
+            name: cmd.get_name().to_owned(),
+            aliases: visible_aliases.into_iter().map(String::from).collect(),
+            hidden_aliases,
+            description: cmd.get_about().unwrap_or_default(),
+            all_flags,
+            args,
+            commands,
+            hidden: cmd.is_hide_set(),
+        }
+    }
+
+    fn valued<Ctx: 'static>(&self, flag: &clap_abs::Arg<'_>, id: id::Valued) -> Type<CompFn<Ctx>> {
+        let (min_values, max_values) = (flag.get_min_num_args(), flag.get_max_num_args());
+        let complete_with_equal =
+            utils::compute_flag_equal_enum(true, min_values, flag.is_require_equals_set(), false)
+                .unwrap_or(CompleteWithEqual::NoNeed);
+        // For boolean flags, possible values is "true" & "false", but they never take values.
+        let comp_options = comp_options(flag);
+        Type::Valued(Valued {
+            id,
+            complete_with_equal,
+            comp_options,
+            min_values,
+            max_values,
+            value_delimiter: flag.get_value_delimiter(),
+        })
+    }
+}
+
+/// Possible values if there are any, like what `generate` does, or else the default by `ValueHint`.
+fn comp_options<Ctx>(arg: &clap_abs::Arg<'_>) -> CompFn<Ctx> {
+    let possible_values = arg.get_possible_values();
+    if possible_values.is_empty() {
+        let default_comp = utils::DefaultComp::new(arg);
+        return Arc::new(move |_, _, arg, _| Ok(default_comp.complete(arg)));
+    }
+    let comps = utils::possible_value_comps(&possible_values);
+    Arc::new(move |_, _, _, _| Ok(comps.clone()))
+}
+
+impl<Ctx> FlagDef for Flag<Ctx> {
+    type Ctx = Ctx;
+    type Comp = CompFn<Ctx>;
+
+    fn ty(&self) -> &Type<CompFn<Ctx>> {
+        &self.ty
+    }
+    fn short(&self) -> &[char] {
+        &self.short
+    }
+    fn long(&self) -> impl Iterator<Item = &str> {
+        self.long.iter().map(String::as_str)
+    }
+    fn description(&self) -> &str {
+        &self.description
+    }
+    fn once(&self) -> bool {
+        self.once
+    }
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+    fn conflicts(&self) -> &[id::Any] {
+        &self.conflicts
+    }
+    fn requires(&self) -> &[id::Any] {
+        &self.requires
+    }
+    fn required(&self) -> bool {
+        self.required
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<Ctx> ArgDef for Arg<Ctx> {
+    type Ctx = Ctx;
+    type Comp = CompFn<Ctx>;
+
+    fn id(&self) -> id::Valued {
+        self.id
+    }
+    fn comp_options(&self) -> &CompFn<Ctx> {
+        &self.comp_options
+    }
+    fn max_values(&self) -> usize {
+        self.max_values
+    }
+    fn last(&self) -> bool {
+        self.last
+    }
+    fn value_delimiter(&self) -> Option<char> {
+        self.value_delimiter
+    }
+    fn required(&self) -> bool {
+        self.required
+    }
+}
+
+impl<Ctx> CommandDef for Command<Ctx> {
+    type Ctx = Ctx;
+    type Flag = Flag<Ctx>;
+    type Arg = Arg<Ctx>;
+
+    fn id(&self) -> id::NoVal {
+        self.id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn aliases(&self) -> impl Iterator<Item = &str> {
+        self.aliases.iter().map(String::as_str)
+    }
+    fn hidden_aliases(&self) -> impl Iterator<Item = &str> {
+        self.hidden_aliases.iter().map(String::as_str)
+    }
+    fn description(&self) -> &str {
+        &self.description
+    }
+    fn all_flags(&self) -> &[Flag<Ctx>] {
+        &self.all_flags
+    }
+    fn args(&self) -> &[Arg<Ctx>] {
+        &self.args
+    }
+    fn commands(&self) -> &[Command<Ctx>] {
+        &self.commands
+    }
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+}
@@ -0,0 +1,268 @@
+use crate::Shell;
+use std::io::{Result as IoResult, Write};
+
+/// How the script calls the binary.
+#[derive(Clone, Copy, Debug)]
+pub enum Invocation<'a> {
+    /// `<bin> <shell> ...`. The `bin` can also be something like `my-bin complete`.
+    Args(&'a str),
+    /// `<var>=<shell> <bin> ...`, see `CompleteEnv`.
+    Env { var: &'a str, bin: &'a str },
+}
+impl<'a> From<&'a str> for Invocation<'a> {
+    fn from(bin: &'a str) -> Self {
+        Invocation::Args(bin)
+    }
+}
+impl Invocation<'_> {
+    /// The command to call the binary with `args`, in the shell's syntax.
+    fn call(&self, shell: Shell, args: &str) -> String {
+        let name = shell.name();
+        match (*self, shell) {
+            (Invocation::Args(bin), Shell::PowerShell) => format!("& {bin} {name} {args}"),
+            (Invocation::Args(bin), Shell::Nushell) => format!("^{bin} {name} {args}"),
+            (Invocation::Args(bin), _) => format!("{bin} {name} {args}"),
+            (Invocation::Env { var, bin }, Shell::Bash | Shell::Zsh | Shell::Fish) => {
+                format!("{var}={name} {bin} {args}")
+            }
+            (Invocation::Env { var, bin }, Shell::PowerShell) => format!(
+                "& {{ $env:{var} = '{name}'; & {bin} @args; Remove-Item env:{var} }} {args}"
+            ),
+            (Invocation::Env { var, bin }, Shell::Elvish) => {
+                format!("{{|@a| tmp E:{var} = {name}; {bin} $@a }} {args}")
+            }
+            (Invocation::Env { var, bin }, Shell::Nushell) => {
+                format!("with-env {{ {var}: {name} }} {{ ^{bin} {args} }}")
+            }
+        }
+    }
+}
+
+impl Shell {
+    /// The name used by `str::parse`, and also by the scripts when calling the binary.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Bash => "bash",
+            Shell::PowerShell => "powershell",
+            Shell::Elvish => "elvish",
+            Shell::Nushell => "nushell",
+        }
+    }
+
+    /// Print the script that registers the completion to the shell, for all the command `names`,
+    /// e.g. `&["git", "g"]` if the user has an alias `g` for `git`.
+    ///
+    /// `bin` is how the script calls the binary, see `Invocation`. Given `Invocation::Args`,
+    /// the binary is called in one of these forms, and should print the completions with
+    /// `CompletionGroup::print` of the same shell:
+    /// - bash, zsh, fish, powershell: `<bin> <shell> --line <line> <cursor>`, where `cursor` is counted in characters.
+    ///   Use `Command::supplement_line` to complete it, so words with quotes and spaces just work.
+    /// - elvish, nushell: `<bin> <shell> <words...>`, where the last word is the one being completed.
+    ///   Use `Command::supplement` to complete it.
+    ///
+    /// Or just use `CompleteEnv`, which handles all of these.
+    ///
+    /// ```
+    /// use supplements::Shell;
+    /// let mut script = vec![];
+    /// Shell::Bash.print_script(&["git", "g"], "/path/to/bin", &mut script).unwrap();
+    /// let script = String::from_utf8(script).unwrap();
+    /// assert!(script.contains("complete -F _supplements_git git g"));
+    /// ```
+    pub fn print_script<'a>(
+        &self,
+        names: &[&str],
+        bin: impl Into<Invocation<'a>>,
+        w: &mut impl Write,
+    ) -> IoResult<()> {
+        let func = match names.first() {
+            Some(name) => name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+            None => {
+                log::warn!("no command name for the script");
+                return Ok(());
+            }
+        };
+        let bin = bin.into();
+        let call = |args: &str| bin.call(*self, args);
+        match self {
+            Shell::Bash => writeln!(
+                w,
+                r#"_supplements_{func}()
+{{
+    local IFS=$'\n'
+    COMPREPLY=($({call}))
+    local last=$((${{#COMPREPLY[@]}} - 1))
+    if [[ $last -ge 0 && ${{COMPREPLY[$last]}} == $'\t'nospace ]]; then
+        unset "COMPREPLY[$last]"
+        compopt -o nospace
+    fi
+}} &&
+    complete -F _supplements_{func} {names}"#,
+                call = call(r#"--line "$COMP_LINE" "$COMP_POINT""#),
+                names = names.join(" "),
+            ),
+            Shell::Zsh => writeln!(
+                w,
+                r#"#compdef {names}
+
+_supplements_{func}() {{
+    # $BUFFER may hold other commands, e.g. `cd x && git <TAB>`, so only pass the current one.
+    local line="${{(j: :)words}}"
+    local cursor=$(( ${{#${{(j: :)words[1,CURRENT]}}}} - ${{#SUFFIX}} ))
+    local -a candidates
+    candidates=("${{(@f)$( {call} )}}")
+
+    local group=''
+    local -a expl=()
+    local -a values
+    local -a descs
+    local -a nsvalues
+    local -a nsdescs
+    for line in $candidates; do
+        if [[ $line == $'\t'* ]]; then
+            parts=(${{(@ps:\t:)line}})
+            if [[ "${{parts[3]}}" == nospace ]]; then
+                nsvalues+=("${{parts[1]}}")
+                nsdescs+=("${{parts[2]}}")
+            else
+                values+=("${{parts[1]}}")
+                descs+=("${{parts[2]}}")
+            fi
+        elif [[ $line == MESSAGE$'\t'* ]]; then
+            _message -r "${{line#*$'\t'}}"
+        else
+            if [[ ! -z "$group" ]]; then
+                if (( ${{#values}} )); then
+                    _wanted $group expl $group compadd -Q -d descs -- ${{values}}
+                fi
+                if (( ${{#nsvalues}} )); then
+                    _wanted $group expl $group compadd -Q -S '' -d nsdescs -- ${{nsvalues}}
+                fi
+            fi
+
+            group=$line
+            values=()
+            descs=()
+            nsvalues=()
+            nsdescs=()
+        fi
+    done
+}}
+
+if [ "$funcstack[1]" = "_supplements_{func}" ]; then
+    _supplements_{func} "$@"
+else
+    compdef _supplements_{func} {names}
+fi"#,
+                call = call(r#"--line "$line" "$cursor""#),
+                names = names.join(" "),
+            ),
+            Shell::Fish => {
+                writeln!(
+                    w,
+                    r#"function __supplements_{func}
+    set -l line (commandline -p | string collect)
+    set -l cursor (commandline -cp | string collect | string length)
+    {call}
+end
+"#,
+                    call = call(r#"--line "$line" $cursor"#),
+                )?;
+                for name in names {
+                    writeln!(w, r#"complete -k -c {name} -x -a "(__supplements_{func})""#)?;
+                }
+                Ok(())
+            }
+            Shell::PowerShell => writeln!(
+                w,
+                r#"Register-ArgumentCompleter -Native -CommandName {names} -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    # The AST doesn't include trailing white spaces, e.g. `git log <TAB>`
+    $cursor = $cursorPosition - $commandAst.Extent.StartOffset
+    $line = $commandAst.ToString().PadRight($cursor)
+    {call} | ForEach-Object {{
+        $parts = $_ -split "`t"
+        [System.Management.Automation.CompletionResult]::new($parts[0], $parts[1], $parts[2], $parts[3])
+    }}
+}}"#,
+                call = call(r#"--line "$line" $cursor"#),
+                names = names
+                    .iter()
+                    .map(|n| format!("'{n}'"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Shell::Elvish => writeln!(
+                w,
+                r#"for name [{names}] {{
+    set edit:completion:arg-completer[$name] = {{|@words|
+        {call} | from-json | each {{|c|
+            edit:complex-candidate $c[value] &display=$c[display] &code-suffix=$c[suffix]
+        }}
+    }}
+}}"#,
+                call = call("$@words"),
+                names = names
+                    .iter()
+                    .map(|n| format!("'{n}'"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            // NOTE: Nushell only has one external completer, so this replaces any existing one.
+            Shell::Nushell => writeln!(
+                w,
+                r#"$env.config.completions.external.enable = true
+$env.config.completions.external.completer = {{|spans|
+    match $spans.0 {{
+        {names} => {{ {call} | from json }}
+        _ => null
+    }}
+}}"#,
+                call = call("...$spans"),
+                names = names
+                    .iter()
+                    .map(|n| format!("\"{n}\""))
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        for shell in [
+            Shell::Zsh,
+            Shell::Fish,
+            Shell::Bash,
+            Shell::PowerShell,
+            Shell::Elvish,
+            Shell::Nushell,
+        ] {
+            assert_eq!(shell.name().parse(), Ok(shell));
+        }
+    }
+
+    #[test]
+    fn test_multiple_names() {
+        let print = |shell: Shell| {
+            let mut w = vec![];
+            shell.print_script(&["my-git", "g"], "bin", &mut w).unwrap();
+            String::from_utf8(w).unwrap()
+        };
+        let script = print(Shell::Fish);
+        assert!(script.contains("complete -k -c my-git -x -a \"(__supplements_my_git)\""));
+        assert!(script.contains("complete -k -c g -x -a \"(__supplements_my_git)\""));
+        assert!(print(Shell::Zsh).starts_with("#compdef my-git g\n"));
+        assert!(print(Shell::PowerShell).contains("-CommandName 'my-git', 'g'"));
+        assert!(print(Shell::Elvish).contains("for name ['my-git' 'g']"));
+        assert!(print(Shell::Nushell).contains("\"my-git\" | \"g\" => {"));
+    }
+}
@@ -14,11 +14,12 @@ use clap4 as clap;
 use args::Git;
 use clap::CommandFactory;
 use std::path::Path;
-use supplements::generate;
+use supplements::{Config, generate};
 
 fn main() {
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
     let file = Path::new(&out_dir).join("definition.rs");
     let mut f = std::fs::File::create(file).unwrap();
-    generate(&mut Git::command(), Default::default(), &mut f).unwrap();
+    let config = Config::default().ctx_type("crate::GitRunner");
+    generate(&mut Git::command(), config, &mut f).unwrap();
 }
@@ -5,8 +5,14 @@ use clap4 as clap;
 
 use clap::{CommandFactory, Parser};
 use std::io::stdout;
+use std::path::PathBuf;
 use std::process::Command;
-use supplements::{Completion, History, Shell, generate, generate_default};
+use std::time::Duration;
+use supplements::cache::Cache;
+use supplements::error::CompError;
+use supplements::{
+    CompResult, CompleteEnv, Completion, Fallback, Guard, History, generate, generate_default,
+};
 use supplements_example::args::Git;
 
 mod def {
@@ -15,34 +21,49 @@ mod def {
 
 use def::Supplements;
 
-fn run_git(args: &str) -> String {
-    let out = Command::new("git")
-        .args(args.split(" "))
-        .output()
-        .unwrap()
-        .stdout;
-    String::from_utf8(out).unwrap()
+/// The context shared by all completers, see `Config::ctx_type` in `build.rs`
+#[derive(Clone)]
+pub struct GitRunner {
+    dir: PathBuf,
+    cache: Cache,
+}
+impl GitRunner {
+    /// Failures like a missing `git` binary or not being in a repo are shown to the user
+    fn run(&self, args: &str) -> Result<String, CompError> {
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args.split(" "))
+            .output()?;
+        if !out.status.success() {
+            let err = String::from_utf8_lossy(&out.stderr);
+            return Err(err.trim().into());
+        }
+        Ok(String::from_utf8(out.stdout)?)
+    }
 }
 impl def::FlagGitDir for Supplements {} // default implementation
 impl def::cmd_checkout::ArgFileOrCommit for Supplements {
     /// For the first argument, it can either be a git commit or a file
-    fn comp_options(_history: &History, _arg: &str) -> Vec<Completion> {
+    fn comp_options(git: &GitRunner, _history: &History, _arg: &str, _index: usize) -> CompResult {
         let mut ret = vec![];
-        for line in run_git("log --oneline -10").lines() {
-            let (hash, description) = line.split_once(" ").unwrap();
-            ret.push(Completion::new(hash, description).group("Commits"));
+        for line in git.run("log --oneline -10")?.lines() {
+            if let Some((hash, description)) = line.split_once(" ") {
+                ret.push(Completion::new(hash, description).group("Commits"));
+            }
         }
-        for line in run_git("status --porcelain").lines() {
-            let (_, file) = line.rsplit_once(" ").unwrap();
-            ret.push(Completion::new(file, "").group("Modified file"));
+        for line in git.run("status --porcelain")?.lines() {
+            if let Some((_, file)) = line.rsplit_once(" ") {
+                ret.push(Completion::new(file, "").group("Modified file"));
+            }
         }
-        ret
+        Ok(ret)
     }
 }
 impl def::cmd_checkout::ArgFiles for Supplements {
     /// For the second and more arguments, it can only be file
     /// Let's also filter out those files we've already seen!
-    fn comp_options(history: &History, _arg: &str) -> Vec<Completion> {
+    fn comp_options(git: &GitRunner, history: &History, _arg: &str, _index: usize) -> CompResult {
         let prev1 = history
             .find(def::cmd_checkout::ID_ARG_FILES)
             .into_iter()
@@ -50,35 +71,59 @@ impl def::cmd_checkout::ArgFiles for Supplements {
         let prev2 = history
             .find(def::cmd_checkout::ID_ARG_FILE_OR_COMMIT)
             .map(|x| &x.value);
-        let prev: Vec<_> = prev1.chain(prev2.into_iter()).collect();
-        run_git("status --porcelain")
+        let prev: Vec<_> = prev1.chain(prev2).collect();
+        let comps = git
+            .run("status --porcelain")?
             .lines()
             .filter_map(|line| {
-                let (_, file) = line.rsplit_once(" ").unwrap();
+                let (_, file) = line.rsplit_once(" ")?;
                 if prev.iter().any(|p| *p == file) {
                     None
                 } else {
                     Some(Completion::new(file, "").group("Modified file"))
                 }
             })
-            .collect()
+            .collect();
+        Ok(comps)
     }
 }
 impl def::cmd_log::ArgCommit for Supplements {
-    fn comp_options(_history: &History, _arg: &str) -> Vec<Completion> {
-        run_git("log --oneline -10")
-            .lines()
-            .map(|line| {
-                let (hash, description) = line.split_once(" ").unwrap();
-                Completion::new(hash, description).group("Commits")
-            })
-            .collect()
+    /// Commits don't change that often, so cache them for a while
+    fn comp_options(git: &GitRunner, history: &History, _arg: &str, _index: usize) -> CompResult {
+        let id = def::cmd_log::ID_ARG_COMMIT;
+        let git_dir = def::ID_FLAG_GIT_DIR.into();
+        let runner = git.clone();
+        git.cache.get_or_update(id, &[git_dir], history, move || {
+            let comps = runner
+                .run("log --oneline -10")?
+                .lines()
+                .filter_map(|line| {
+                    let (hash, description) = line.split_once(" ")?;
+                    Some(Completion::new(hash, description).group("Commits"))
+                })
+                .collect();
+            Ok(comps)
+        })
     }
 }
 
 fn main() {
     env_logger::init();
 
+    // e.g. `COMPLETE=fish qit --line "qit log --gr" 12`, or `COMPLETE=fish qit` to print the script.
+    // Editors can use `COMPLETE=json qit qit log --gr` to get JSON instead.
+    let git = GitRunner {
+        dir: std::env::current_dir().unwrap(),
+        cache: Cache::new("supplements-example").ttl(Duration::from_secs(5)),
+    };
+    // `git log` can be slow in a huge repo, don't let it freeze the prompt
+    let guard = Guard::default()
+        .timeout(Duration::from_millis(500))
+        .fallback(Fallback::Files);
+    CompleteEnv::with_ctx(&def::CMD, &git)
+        .guard(&guard)
+        .complete();
+
     let args: Vec<_> = std::env::args().collect();
     log::info!("args = {:?}", args);
 
@@ -88,28 +133,9 @@ fn main() {
         return;
     }
 
-    if args.get(1).map(|s| s.as_str()) == Some("parse") {
-        let res = Git::try_parse_from(args[1..].iter());
-        match res {
-            Ok(res) => println!("{:?}", res),
-            Err(err) => println!("{err}"),
-        }
-        return;
-    }
-
-    let shell: Result<Shell, _> = args.get(1).unwrap().parse();
-    match shell {
-        Err(_) => {
-            let res = Git::try_parse_from(args);
-            match res {
-                Ok(res) => println!("{:?}", res),
-                Err(err) => println!("{err}"),
-            }
-        }
-        Ok(shell) => {
-            let args = args[2..].iter().map(String::from);
-            let comps = def::CMD.supplement(args).unwrap();
-            comps.print(shell, &mut stdout()).unwrap();
-        }
+    let res = Git::try_parse_from(args);
+    match res {
+        Ok(res) => println!("{:?}", res),
+        Err(err) => println!("{err}"),
     }
 }
@@ -12,7 +12,8 @@ fn main() {
         .ignore(&["ignored-cmd"])
         .ignore(&["checkout", "flag1"])
         .ignore(&["log", "flag2"])
-        .ignore(&["flag3"]);
+        .ignore(&["flag3"])
+        .requires(&["log", "stat"], &["graph"]);
 
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
     let file = Path::new(&out_dir).join("definition.rs");
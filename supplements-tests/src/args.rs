@@ -1,11 +1,11 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, ValueEnum, ValueHint};
 
 #[derive(Parser, Debug)]
 pub struct Arg {
     #[clap(long, global = true)]
     pub flag3: Option<std::path::PathBuf>,
 
-    #[clap(long, global = true)]
+    #[clap(long, global = true, value_hint = ValueHint::DirPath)]
     pub git_dir: Option<std::path::PathBuf>,
     #[clap(subcommand)]
     pub sub: SubCommand,
@@ -15,20 +15,44 @@ pub enum SubCommand {
     Checkout {
         #[clap(long)]
         flag1: Option<String>, // ignored
+        #[clap(long, group = "format")]
+        json: bool,
+        #[clap(long, group = "format")]
+        yaml: bool,
+        #[clap(long, exclusive = true)]
+        abort: bool,
+        #[clap(long, conflicts_with = "file_or_commit")]
+        orphan: bool,
+        #[clap(long, num_args = 2)]
+        range: Option<Vec<String>>,
         file_or_commit: Option<String>,
         files: Vec<std::path::PathBuf>,
     },
-    #[clap(about = "log")]
+    #[clap(about = "log", visible_alias = "lg", alias = "l")]
     Log {
         #[clap(long)]
         graph: bool,
         #[clap(long)]
         pretty: Option<Pretty>,
+        #[clap(long, value_delimiter = ',')]
+        formats: Vec<Pretty>,
         commit: Option<String>,
         #[clap(long)]
         flag1: Option<String>,
         #[clap(long)]
         flag2: bool, // ignored
+        #[clap(long, hide = true)]
+        debug: bool,
+        #[clap(long)]
+        stat: bool,
+        #[clap(long, required = true, value_hint = ValueHint::Other)]
+        author: Option<String>,
+    },
+    Switch {
+        #[clap(long, value_hint = ValueHint::ExecutablePath)]
+        hook: Option<std::path::PathBuf>,
+        mode: Mode,
+        branch: Option<String>,
     },
     #[clap(about = "log")]
     IgnoredCmd { arg: Option<String> },
@@ -36,6 +60,14 @@ pub enum SubCommand {
     Other(#[allow(unused)] Vec<String>),
 }
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Mode {
+    #[clap(help = "Keep \"local\" changes", alias = "k")]
+    Keep,
+    Force,
+    #[clap(hide = true)]
+    Legacy,
+}
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum Pretty {
     #[clap(help = "<sha1> <title line>")]
     Oneline,
@@ -25,15 +25,164 @@ mod test {
         assert_eq!(vec!["--git-dir"], map_comps(&comps));
 
         let comps = run("git g").unwrap();
-        assert_eq!(vec!["checkout", "log"], map_comps(&comps));
+        assert_eq!(vec!["checkout", "lg", "log", "switch"], map_comps(&comps));
 
         let comps = run("git log -").unwrap();
         assert_eq!(
-            vec!["--flag1", "--git-dir", "--graph", "--pretty"],
+            vec![
+                "--author",
+                "--flag1",
+                "--formats",
+                "--git-dir",
+                "--graph",
+                "--pretty"
+            ],
             map_comps(&comps)
         );
 
         let comps = run("git checkout -").unwrap();
-        assert_eq!(vec!["--git-dir"], map_comps(&comps));
+        assert_eq!(
+            vec![
+                "--abort",
+                "--git-dir",
+                "--json",
+                "--orphan",
+                "--range",
+                "--yaml"
+            ],
+            map_comps(&comps)
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let expected = vec![
+            "--author",
+            "--flag1",
+            "--formats",
+            "--git-dir",
+            "--graph",
+            "--pretty",
+        ];
+        let comps = run("git lg -").unwrap();
+        assert_eq!(expected, map_comps(&comps));
+        let comps = run("git l -").unwrap();
+        assert_eq!(expected, map_comps(&comps));
+    }
+
+    #[test]
+    fn test_hidden() {
+        let comps = run("git log --deb").unwrap();
+        assert_eq!(vec!["--debug"], map_comps(&comps));
+        let comps = run("git log --debug --").unwrap();
+        assert_eq!(
+            vec![
+                "--author",
+                "--flag1",
+                "--formats",
+                "--git-dir",
+                "--graph",
+                "--pretty"
+            ],
+            map_comps(&comps)
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let comps = run("git checkout --json -").unwrap();
+        assert_eq!(vec!["--git-dir", "--orphan", "--range"], map_comps(&comps));
+        let comps = run("git checkout main -").unwrap();
+        assert_eq!(
+            vec!["--git-dir", "--json", "--range", "--yaml"],
+            map_comps(&comps)
+        );
+        let err = run("git checkout --abort -").unwrap_err();
+        assert_eq!(err, supplements::error::Error::UnexpectedFlag);
+    }
+
+    #[test]
+    fn test_requires() {
+        let comps = run("git log -").unwrap();
+        assert!(!map_comps(&comps).contains(&"--stat"));
+        let comps = run("git log --graph -").unwrap();
+        assert!(map_comps(&comps).contains(&"--stat"));
+        // Only listing depends on it, the flag still parses without `--graph`
+        let comps = run("git log --stat -").unwrap();
+        assert!(map_comps(&comps).contains(&"--graph"));
+    }
+
+    #[test]
+    fn test_required() {
+        let comps = run("git log -").unwrap();
+        let first = &comps.inner().0[0];
+        assert_eq!(("--author", Some("required")), (&*first.value, first.group));
+
+        let comps = run("git log --author x -").unwrap();
+        assert!(comps.inner().0.iter().all(|c| c.group.is_none()));
+
+        let comps = run("git switch ").unwrap();
+        assert!(comps.inner().0.iter().all(|c| c.group == Some("required")));
+    }
+
+    #[test]
+    fn test_multi_values() {
+        let comps = run("git checkout --range a b --").unwrap();
+        assert_eq!(
+            vec!["--git-dir", "--json", "--orphan", "--range", "--yaml"],
+            map_comps(&comps)
+        );
+        let err = run("git checkout --range a --json -").unwrap_err();
+        assert_eq!(
+            err,
+            supplements::error::Error::FlagNoValue("range".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_value_hint() {
+        let comps = run("git log --author ").unwrap();
+        assert_eq!(Vec::<&str>::new(), map_comps(&comps));
+
+        let comps = run("git --git-dir ").unwrap();
+        let comps = map_comps(&comps);
+        assert!(comps.contains(&"src/"));
+        assert!(comps.iter().all(|c| c.ends_with('/')));
+
+        // Nothing here is executable, so only directories are left
+        let comps = run("git switch --hook ").unwrap();
+        let comps = map_comps(&comps);
+        assert!(comps.contains(&"src/"));
+        assert!(!comps.contains(&"Cargo.toml"));
+        #[cfg(unix)]
+        {
+            let comps = run("git switch --hook /bin/s").unwrap();
+            assert!(map_comps(&comps).contains(&"/bin/sh"));
+        }
+    }
+
+    #[test]
+    fn test_arg_possible_values() {
+        let comps = run("git switch ").unwrap();
+        assert_eq!(vec!["force", "keep"], map_comps(&comps));
+        let desc: Vec<_> = comps.inner().0.iter().map(|c| &c.description).collect();
+        assert!(desc.contains(&&"Keep \"local\" changes".to_owned()));
+
+        // Aliases and hidden values are accepted
+        for mode in ["k", "legacy"] {
+            let comps = run(&format!("git switch {mode} -")).unwrap();
+            assert_eq!(vec!["--git-dir", "--hook"], map_comps(&comps));
+        }
+    }
+
+    #[test]
+    fn test_value_delimiter() {
+        let comps = run("git log --formats short,").unwrap();
+        assert_eq!(vec!["short,full", "short,oneline"], map_comps(&comps));
+        let comps = run("git log --formats=full,sh").unwrap();
+        assert_eq!(
+            vec!["--formats=full,oneline", "--formats=full,short"],
+            map_comps(&comps)
+        );
     }
 }
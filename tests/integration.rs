@@ -1,5 +1,6 @@
 use error::Error;
 use history::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use supplements::*;
 
 mod def {
@@ -7,78 +8,250 @@ mod def {
 
     pub const C_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
     pub const C_FLAG: Flag = Flag {
-        id: id::Flag::No(C_FLAG_ID),
+        ty: flag_type::Type::new_bool(C_FLAG_ID),
         short: &['c'],
         long: &["long-c", "long-c-2"],
         description: "test description for flag C",
-        comp_options: None,
         once: true,
-        complete_with_equal: CompleteWithEqual::NoNeed,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
     };
     pub const B_FLAG_ID: id::SingleVal = id::SingleVal::new(line!(), "");
     pub const B_FLAG: Flag = Flag {
-        id: id::Flag::Single(B_FLAG_ID),
+        ty: flag_type::Type::new_valued(
+            id::Valued::Single(B_FLAG_ID),
+            CompleteWithEqual::NoNeed,
+            b_flag_comp_options,
+        ),
         short: &['b', 'x'],
         long: &["long-b"],
         description: "test description for flag B",
-        comp_options: Some(|_history, arg| {
-            let mut ret = vec![];
-            if arg != "" {
-                ret.push(Completion::new(arg, ""));
-            }
-            ret.push(Completion::new(&format!("{arg}!"), ""));
-            ret
-        }),
         once: true,
-        complete_with_equal: CompleteWithEqual::NoNeed,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
     };
+    pub fn b_flag_comp_options(
+        _ctx: &(),
+        _history: &History,
+        arg: &str,
+        _index: usize,
+    ) -> CompResult {
+        let mut ret = vec![];
+        if !arg.is_empty() {
+            ret.push(Completion::new(arg, ""));
+        }
+        ret.push(Completion::new(&format!("{arg}!"), ""));
+        Ok(ret)
+    }
     pub const A_ARG_ID: id::SingleVal = id::SingleVal::new(line!(), "");
     pub const A_ARG: Arg = Arg {
-        id: id::Arg::Single(A_ARG_ID),
-        comp_options: |_, _| {
-            vec![
+        id: id::Valued::Single(A_ARG_ID),
+        comp_options: |_, _, _, _| {
+            Ok(vec![
                 Completion::new("arg-option1", ""),
                 Completion::new("arg-option2", ""),
-            ]
+            ])
         },
         max_values: 1,
+        last: false,
+        value_delimiter: None,
+        required: false,
     };
     pub const ROOT_ID: id::NoVal = id::NoVal::new(line!(), "");
     pub const ROOT: Command = Command {
         id: ROOT_ID,
-        all_flags: &[B_FLAG, C_FLAG, OPT_FLAG],
+        all_flags: &[B_FLAG, C_FLAG, OPT_FLAG, DEBUG_FLAG],
         name: "root",
+        aliases: &[],
+        hidden_aliases: &[],
         description: "",
-        args: &[A_ARG, D_ARG],
-        commands: &[SUB],
+        args: &[A_ARG, D_ARG, LAST_ARG],
+        commands: &[SUB, HIDDEN_SUB],
+        hidden: false,
     };
     pub const SUB_ID: id::NoVal = id::NoVal::new(line!(), "");
     pub const SUB: Command = Command {
         id: SUB_ID,
-        all_flags: &[B_FLAG],
+        all_flags: &[B_FLAG, JSON_FLAG, YAML_FLAG, ORPHAN_FLAG, NAME_FLAG],
         name: "sub",
+        aliases: &[],
+        hidden_aliases: &[],
         description: "test sub description",
         args: &[A_ARG, A_ARG],
         commands: &[],
+        hidden: false,
     };
     pub const D_ARG_ID: id::MultiVal = id::MultiVal::new(line!(), "");
     pub const D_ARG: Arg = Arg {
-        id: id::Arg::Multi(D_ARG_ID),
-        comp_options: |_, _| vec![Completion::new("d-arg!", "")],
+        id: id::Valued::Multi(D_ARG_ID),
+        comp_options: |_, _, _, _| Ok(vec![Completion::new("d-arg!", "")]),
         max_values: 2,
+        last: false,
+        value_delimiter: None,
+        required: false,
+    };
+    pub const LAST_ARG_ID: id::MultiVal = id::MultiVal::new(line!(), "");
+    pub const LAST_ARG: Arg = Arg {
+        id: id::Valued::Multi(LAST_ARG_ID),
+        comp_options: |_, _, _, _| Ok(vec![Completion::new("last-arg!", "")]),
+        max_values: usize::MAX,
+        last: true,
+        value_delimiter: None,
+        required: false,
+    };
+
+    pub const JSON_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const JSON_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_bool(JSON_FLAG_ID),
+        short: &[],
+        long: &["json"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[id::Any::No(YAML_FLAG_ID)],
+        requires: &[],
+        required: false,
+    };
+    pub const YAML_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const YAML_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_bool(YAML_FLAG_ID),
+        short: &[],
+        long: &["yaml"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[id::Any::No(JSON_FLAG_ID)],
+        requires: &[],
+        required: false,
+    };
+    pub const ORPHAN_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const ORPHAN_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_bool(ORPHAN_FLAG_ID),
+        short: &[],
+        long: &["orphan"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[id::Any::Single(A_ARG_ID)],
+        requires: &[],
+        required: false,
+    };
+
+    pub const NAME_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const NAME_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_bool(NAME_FLAG_ID),
+        short: &[],
+        long: &["name"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: true,
+    };
+
+    pub const HIDDEN_SUB_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const HIDDEN_SUB: Command = Command {
+        id: HIDDEN_SUB_ID,
+        all_flags: &[POINT_FLAG, FILES_FLAG, FEATURES_FLAG],
+        name: "hidden-sub",
+        aliases: &[],
+        hidden_aliases: &[],
+        description: "",
+        args: &[],
+        commands: &[],
+        hidden: true,
+    };
+    pub const POINT_FLAG_ID: id::MultiVal = id::MultiVal::new(line!(), "point");
+    pub const POINT_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_valued(
+            id::Valued::Multi(POINT_FLAG_ID),
+            CompleteWithEqual::NoNeed,
+            |_ctx, _history, _arg, index| Ok(vec![Completion::new(["x!", "y!"][index], "")]),
+        )
+        .with_num_values(2, 2),
+        short: &[],
+        long: &["point"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
+    };
+    pub const FILES_FLAG_ID: id::MultiVal = id::MultiVal::new(line!(), "");
+    pub const FILES_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_valued(
+            id::Valued::Multi(FILES_FLAG_ID),
+            CompleteWithEqual::NoNeed,
+            |_ctx, _history, _arg, index| Ok(vec![Completion::new(&format!("file{index}!"), "")]),
+        )
+        .with_num_values(1, usize::MAX),
+        short: &[],
+        long: &["files"],
+        description: "",
+        once: false,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
+    };
+    pub const FEATURES_FLAG_ID: id::MultiVal = id::MultiVal::new(line!(), "");
+    pub const FEATURES_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_valued(
+            id::Valued::Multi(FEATURES_FLAG_ID),
+            CompleteWithEqual::NoNeed,
+            |_ctx, _history, _arg, _index| {
+                Ok(["a", "b", "c"].map(|s| Completion::new(s, "")).to_vec())
+            },
+        )
+        .with_value_delimiter(','),
+        short: &[],
+        long: &["features"],
+        description: "",
+        once: true,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
+    };
+    pub const DEBUG_FLAG_ID: id::NoVal = id::NoVal::new(line!(), "");
+    pub const DEBUG_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_bool(DEBUG_FLAG_ID),
+        short: &['d'],
+        long: &["debug"],
+        description: "",
+        once: true,
+        hidden: true,
+        conflicts: &[],
+        requires: &[],
+        required: false,
     };
 
     pub const OPT_FLAG_ID: id::SingleVal = id::SingleVal::new(line!(), "");
     pub const OPT_FLAG: Flag = Flag {
-        id: id::Flag::Single(OPT_FLAG_ID),
+        ty: flag_type::Type::new_valued(
+            id::Valued::Single(OPT_FLAG_ID),
+            CompleteWithEqual::Optional,
+            |_ctx, _history, _arg, _index| {
+                Ok(vec![
+                    Completion::new("opt1", ""),
+                    Completion::new("opt2", ""),
+                ])
+            },
+        ),
         short: &['o'],
         long: &["opt"],
         description: "test description for flag OPT",
-        comp_options: Some(|_history, _arg| {
-            vec![Completion::new("opt1", ""), Completion::new("opt2", "")]
-        }),
         once: true,
-        complete_with_equal: CompleteWithEqual::Optional,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
     };
 }
 
@@ -92,7 +265,7 @@ fn try_run(args: &str, last_is_empty: bool) -> (Vec<HistoryUnit>, Result<Vec<Com
     } else {
         None
     };
-    let args = args.chain(last.into_iter());
+    let args = args.chain(last);
     let mut history = History::default();
     let res = def::ROOT.supplement_with_history(&mut history, args);
     let res = res.map(|r| r.into_inner().0);
@@ -102,6 +275,14 @@ fn run(args: &str, last_is_empty: bool) -> (Vec<HistoryUnit>, Vec<Completion>) {
     let (h, r) = try_run(args, last_is_empty);
     (h, r.unwrap())
 }
+fn run_at(args: &str, cursor: usize, offset: Option<usize>) -> Result<Vec<Completion>> {
+    let _ = env_logger::try_init();
+
+    let args = args.split(' ').map(|s| s.to_owned());
+    let args = std::iter::once("whatever".to_owned()).chain(args);
+    let res = def::ROOT.supplement_at(args, cursor, offset);
+    res.map(|r| r.into_inner().0)
+}
 fn map_comp_values(arr: &[Completion]) -> Vec<&str> {
     let mut v: Vec<_> = arr.iter().map(|c| &*c.value).collect();
     v.sort();
@@ -144,7 +325,7 @@ fn test_args_last() {
 fn test_flags_not_last() {
     let expected = (
         vec![no!(C_FLAG_ID), single!(B_FLAG_ID, "option"), no!(SUB_ID)],
-        (def::A_ARG.comp_options)(&Default::default(), ""),
+        (def::A_ARG.comp_options)(&(), &Default::default(), "", 0).unwrap(),
     );
 
     let res = run("-c --long-b=option sub", true);
@@ -184,6 +365,9 @@ fn test_flags_last() {
     let (h, r) = run("-c --long-b=x", false);
     assert_eq!(expected_h, h);
     assert_eq!(map_comp_values(&r), vec!["--long-b=x", "--long-b=x!"]);
+    // Only the value itself is shown
+    let display: Vec<_> = r.iter().map(|c| c.display.as_deref()).collect();
+    assert_eq!(display, vec![Some("x"), Some("x!")]);
 
     let (h, r) = run("-c -b=x", false);
     assert_eq!(expected_h, h);
@@ -210,7 +394,7 @@ fn test_flags_last() {
 fn test_flags_supplement() {
     let expected = (
         vec![no!(C_FLAG_ID)],
-        (def::B_FLAG.comp_options.unwrap())(&Default::default(), "x"),
+        def::b_flag_comp_options(&(), &Default::default(), "x", 0).unwrap(),
     );
 
     let res = run("-c --long-b x", false);
@@ -253,7 +437,10 @@ fn test_fall_back_and_var_len_arg() {
 fn test_flag_after_args() {
     let (h, r) = run("sub arg1 --", false);
     assert_eq!(h, vec![no!(SUB_ID), single!(A_ARG_ID, "arg1")]);
-    assert_eq!(map_comp_values(&r), vec!["--long-b"],);
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--json", "--long-b", "--name", "--yaml"],
+    );
 
     let (h, r) = run("sub arg1 --long-b flag1", false);
     assert_eq!(h, vec![no!(SUB_ID), single!(A_ARG_ID, "arg1")]);
@@ -346,12 +533,442 @@ fn test_optional_flag() {
     let (h, r) = run("-c", false);
     assert_eq!(h, vec![no!(C_FLAG_ID)]);
     assert_eq!(map_comp_values(&r), vec!["-cb", "-co", "-co="]);
+    // Only the `-co=` stub is half way there
+    let no_space: Vec<_> = r.iter().filter(|c| c.no_space).map(|c| &c.value).collect();
+    assert_eq!(no_space, vec!["-co="]);
 
     let (h, r) = run("-co", false);
     assert_eq!(h, vec![no!(C_FLAG_ID)]);
     assert_eq!(map_comp_values(&r), vec!["-co", "-co=opt1", "-co=opt2"]);
+    assert!(r.iter().all(|c| !c.no_space));
 
     let (h, r) = try_run("-oz", false);
     assert_eq!(h, vec![single!(OPT_FLAG_ID, "")]);
     assert_eq!(r.unwrap_err(), Error::FlagNotFound("z".to_owned()));
 }
+
+#[test]
+fn test_double_dash() {
+    let (h, r) = run("sub -- -b", true);
+    assert_eq!(h, vec![no!(SUB_ID), single!(A_ARG_ID, "-b")]);
+    assert_eq!(map_comp_values(&r), vec!["arg-option1", "arg-option2"]);
+
+    let (h, r) = run("sub -- -", false);
+    assert_eq!(h, vec![no!(SUB_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["arg-option1", "arg-option2"]);
+
+    let (h, r) = run("sub a1 -- --long-b", false);
+    assert_eq!(h, vec![no!(SUB_ID), single!(A_ARG_ID, "a1")]);
+    assert_eq!(map_comp_values(&r), vec!["arg-option1", "arg-option2"]);
+
+    let (h, r) = try_run("sub a1 -- a2 --long-b", false);
+    assert_eq!(h, vec![no!(SUB_ID), single!(A_ARG_ID, "a2")]);
+    assert_eq!(r.unwrap_err(), Error::UnexpectedArg("--long-b".to_owned()));
+}
+
+#[test]
+fn test_last_arg() {
+    let (h, r) = run("-c -- sub -c", true);
+    assert_eq!(h, vec![no!(C_FLAG_ID), multi!(LAST_ARG_ID, ["sub", "-c"])]);
+    assert_eq!(map_comp_values(&r), vec!["last-arg!"]);
+
+    let (h, r) = run("-c -- -", false);
+    assert_eq!(h, vec![no!(C_FLAG_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["last-arg!"]);
+
+    let (h, r) = run("--", false);
+    assert_eq!(h, vec![]);
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--long-b", "--long-c", "--opt", "--opt="]
+    );
+}
+
+#[test]
+fn test_hidden() {
+    let (h, r) = run("--deb", false);
+    assert_eq!(h, vec![]);
+    assert_eq!(map_comp_values(&r), vec!["--debug"]);
+
+    let (h, r) = run("--debug --long", false);
+    assert_eq!(h, vec![no!(DEBUG_FLAG_ID)]);
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--long-b", "--long-c", "--opt", "--opt="]
+    );
+
+    let (h, r) = run("-cd", false);
+    assert_eq!(h, vec![no!(C_FLAG_ID), no!(DEBUG_FLAG_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["-cdb", "-cdo", "-cdo="]);
+
+    let (h, r) = run("hid", false);
+    assert_eq!(h, vec![]);
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["arg-option1", "arg-option2", "hidden-sub"]
+    );
+
+    let (h, r) = try_run("hidden-sub", true);
+    assert_eq!(h, vec![no!(HIDDEN_SUB_ID)]);
+    assert_eq!(r.unwrap_err(), Error::UnexpectedArg("".to_owned()));
+}
+
+#[test]
+fn test_conflicts() {
+    let (h, r) = run("sub --json --", false);
+    assert_eq!(h, vec![no!(SUB_ID), no!(JSON_FLAG_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["--long-b", "--name", "--orphan"]);
+
+    let (h, r) = run("sub --orphan a1 --", false);
+    assert_eq!(
+        h,
+        vec![no!(SUB_ID), no!(ORPHAN_FLAG_ID), single!(A_ARG_ID, "a1")]
+    );
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--json", "--long-b", "--name", "--yaml"]
+    );
+
+    // A conflicting flag that's already typed is still parsed
+    let (h, r) = run("sub --yaml --json --", false);
+    assert_eq!(h, vec![no!(SUB_ID), no!(YAML_FLAG_ID), no!(JSON_FLAG_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["--long-b", "--name", "--orphan"]);
+}
+
+#[test]
+fn test_required() {
+    let (_, r) = run("sub --", false);
+    assert_eq!(r[0], Completion::new("--name", "name").group("required"));
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--json", "--long-b", "--name", "--orphan", "--yaml"]
+    );
+
+    let (_, r) = run("sub --name --", false);
+    assert!(r.iter().all(|c| c.group.is_none()));
+}
+
+#[test]
+fn test_required_arg() {
+    const REQUIRED_ARG: Arg = Arg {
+        id: id::Valued::Single(id::SingleVal::new(line!(), "")),
+        comp_options: |_, _, _, _| {
+            Ok(vec![
+                Completion::new("plain", ""),
+                Completion::new("grouped", "").group("mine"),
+            ])
+        },
+        max_values: 1,
+        last: false,
+        value_delimiter: None,
+        required: true,
+    };
+    const CMD: Command = Command {
+        id: id::NoVal::new(line!(), ""),
+        name: "cmd",
+        aliases: &[],
+        hidden_aliases: &[],
+        description: "",
+        all_flags: &[],
+        args: &[REQUIRED_ARG],
+        commands: &[def::SUB],
+        hidden: false,
+    };
+    let args = ["cmd", ""].into_iter().map(String::from);
+    let r = CMD.supplement(args).unwrap().into_inner().0;
+    let groups: Vec<_> = r.iter().map(|c| (&*c.value, c.group)).collect();
+    assert_eq!(
+        groups,
+        vec![
+            ("plain", Some("required")),
+            ("grouped", Some("mine")),
+            ("sub", Some("command"))
+        ]
+    );
+}
+
+#[test]
+fn test_multi_value_flag() {
+    let (h, r) = run("hidden-sub --point", true);
+    assert_eq!(h, vec![no!(HIDDEN_SUB_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["x!"]);
+
+    let (h, r) = run("hidden-sub --point 1", true);
+    assert_eq!(h, vec![no!(HIDDEN_SUB_ID), multi!(POINT_FLAG_ID, ["1"])]);
+    assert_eq!(map_comp_values(&r), vec!["y!"]);
+
+    let (h, r) = run("hidden-sub --point 1 2 --", false);
+    assert_eq!(
+        h,
+        vec![no!(HIDDEN_SUB_ID), multi!(POINT_FLAG_ID, ["1", "2"])]
+    );
+    assert_eq!(map_comp_values(&r), vec!["--features", "--files"]);
+
+    let (h, r) = try_run("hidden-sub --point 1 --files", true);
+    assert_eq!(h, vec![no!(HIDDEN_SUB_ID), multi!(POINT_FLAG_ID, ["1"])]);
+    assert_eq!(r.unwrap_err(), Error::FlagNoValue("point".to_owned()));
+
+    // Flags with unbounded values keep consuming until some flag appears.
+    let (h, r) = run("hidden-sub --files a b", true);
+    assert_eq!(
+        h,
+        vec![no!(HIDDEN_SUB_ID), multi!(FILES_FLAG_ID, ["a", "b"])]
+    );
+    assert_eq!(map_comp_values(&r), vec!["file2!"]);
+
+    // The value after `=` is always the first one
+    let (_, r) = run("hidden-sub --files=", false);
+    assert_eq!(map_comp_values(&r), vec!["--files=file0!"]);
+
+    let (h, r) = run("hidden-sub --files a --files b --", false);
+    assert_eq!(
+        h,
+        vec![no!(HIDDEN_SUB_ID), multi!(FILES_FLAG_ID, ["a", "b"])]
+    );
+    assert_eq!(
+        map_comp_values(&r),
+        vec!["--features", "--files", "--point"]
+    );
+}
+
+#[test]
+fn test_value_delimiter() {
+    let (h, r) = run("hidden-sub --features", true);
+    assert_eq!(h, vec![no!(HIDDEN_SUB_ID)]);
+    assert_eq!(map_comp_values(&r), vec!["a", "b", "c"]);
+
+    let (_, r) = run("hidden-sub --features a,", false);
+    assert_eq!(map_comp_values(&r), vec!["a,b", "a,c"]);
+
+    let (_, r) = run("hidden-sub --features=c,a,", false);
+    assert_eq!(map_comp_values(&r), vec!["--features=c,a,b"]);
+    assert_eq!(r[0].display.as_deref(), Some("b"));
+
+    let (h, r) = run("hidden-sub --features a,b --", false);
+    assert_eq!(
+        h,
+        vec![no!(HIDDEN_SUB_ID), multi!(FEATURES_FLAG_ID, ["a", "b"])]
+    );
+    assert_eq!(map_comp_values(&r), vec!["--files", "--point"]);
+}
+
+#[test]
+fn test_cursor() {
+    let r = run_at("sub -- --yaml", 2, None).unwrap();
+    assert_eq!(map_comp_values(&r), vec!["--long-b", "--name", "--orphan"]);
+
+    let r = run_at("sub --json --yaml", 2, Some(2)).unwrap();
+    assert_eq!(map_comp_values(&r), vec!["--long-b", "--name", "--orphan"]);
+
+    let r = run_at("sub --json", 3, None).unwrap();
+    assert_eq!(map_comp_values(&r), vec!["arg-option1", "arg-option2"]);
+
+    let r = run_at("-c --long-b=x a1", 1, None).unwrap();
+    assert_eq!(map_comp_values(&r), vec!["-co", "-co="]);
+
+    let r = run_at("sub", 3, None);
+    assert_eq!(r.unwrap_err(), Error::CursorOutOfRange(3));
+}
+
+#[test]
+fn test_cursor_trailing() {
+    // The cursor is on a flag value, and the words after it still show up in history.
+    let mut history = History::default();
+    let args = "whatever sub --long-b x --json"
+        .split(' ')
+        .map(String::from);
+    let grp = def::ROOT.supplement_at_with_history(&mut history, args, 3, None);
+    assert_eq!(
+        map_comp_values(&grp.unwrap().into_inner().0),
+        vec!["x", "x!"]
+    );
+    assert_eq!(history.into_inner(), vec![no!(SUB_ID), no!(JSON_FLAG_ID)]);
+
+    // Completers are never called for the words after the cursor.
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    const COUNTED_FLAG: Flag = Flag {
+        ty: flag_type::Type::new_valued(
+            id::Valued::Single(id::SingleVal::new(line!(), "")),
+            CompleteWithEqual::NoNeed,
+            |_, _, _, _| {
+                CALLS.fetch_add(1, Ordering::Relaxed);
+                Ok(vec![])
+            },
+        ),
+        short: &[],
+        long: &["counted"],
+        description: "",
+        once: false,
+        hidden: false,
+        conflicts: &[],
+        requires: &[],
+        required: false,
+    };
+    const CMD: Command = Command {
+        id: id::NoVal::new(line!(), ""),
+        name: "cmd",
+        aliases: &[],
+        hidden_aliases: &[],
+        description: "",
+        all_flags: &[COUNTED_FLAG],
+        args: &[],
+        commands: &[],
+        hidden: false,
+    };
+    let args = ["cmd", "-", "--counted", "x"].into_iter().map(String::from);
+    let r = CMD.supplement_at(args, 1, None).unwrap().into_inner().0;
+    assert_eq!(map_comp_values(&r), vec!["--counted"]);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_cursor_trailing_positional() {
+    const FIRST_ID: id::SingleVal = id::SingleVal::new(line!(), "");
+    const SECOND_ID: id::SingleVal = id::SingleVal::new(line!(), "");
+    const fn arg(id: id::SingleVal) -> Arg {
+        Arg {
+            id: id::Valued::Single(id),
+            comp_options: |_, _, arg, _| Ok(vec![Completion::new(&format!("{arg}!"), "")]),
+            max_values: 1,
+            last: false,
+            value_delimiter: None,
+            required: false,
+        }
+    }
+    const CMD: Command = Command {
+        id: id::NoVal::new(line!(), ""),
+        name: "cmd",
+        aliases: &[],
+        hidden_aliases: &[],
+        description: "",
+        all_flags: &[],
+        args: &[arg(FIRST_ID), arg(SECOND_ID)],
+        commands: &[],
+        hidden: false,
+    };
+    // The cursor word is the first positional, so `b` is the second one.
+    let mut history = History::default();
+    let args = ["cmd", "", "b"].into_iter().map(String::from);
+    let grp = CMD.supplement_at_with_history(&mut history, args, 1, None);
+    assert_eq!(map_comp_values(&grp.unwrap().into_inner().0), vec!["!"]);
+    let second = HistoryUnitSingleVal {
+        id: SECOND_ID,
+        value: "b".to_owned(),
+    };
+    assert_eq!(history.into_inner(), vec![HistoryUnit::Single(second)]);
+}
+
+#[test]
+fn test_line() {
+    let grp = def::ROOT
+        .supplement_line(r#"whatever 'sub' "arg"#, 100)
+        .unwrap();
+    assert_eq!(grp.open_quote(), Some(line::Quote::Double));
+    let (r, arg) = grp.into_inner();
+    assert_eq!(arg, "arg");
+    assert_eq!(map_comp_values(&r), vec!["arg-option1", "arg-option2"]);
+
+    let grp = def::ROOT
+        .supplement_line("whatever sub -- --yaml # comment", 15)
+        .unwrap();
+    assert_eq!(grp.open_quote(), None);
+    let (r, _) = grp.into_inner();
+    assert_eq!(map_comp_values(&r), vec!["--long-b", "--name", "--orphan"]);
+}
+
+#[test]
+fn test_complete_env() {
+    let try_complete = |env: &CompleteEnv, args: &[&str], var: Option<&str>| {
+        let args = args.iter().map(|s| s.to_string());
+        let mut w = vec![];
+        let triggered = env
+            .try_complete(args, var.map(String::from), &mut w)
+            .unwrap();
+        (triggered, String::from_utf8(w).unwrap())
+    };
+    let env = CompleteEnv::new(&def::ROOT);
+
+    let (triggered, out) = try_complete(&env, &["whatever", "sub"], None);
+    assert_eq!((triggered, out.as_str()), (false, ""));
+    // Like clap_complete, an empty value or `0` is not a trigger.
+    for var in ["", "0"] {
+        let (triggered, out) = try_complete(&env, &["whatever", "sub"], Some(var));
+        assert_eq!((triggered, out.as_str()), (false, ""));
+    }
+
+    let line = &["whatever", "--line", "whatever sub --js --yaml", "17"];
+    let (triggered, out) = try_complete(&env, line, Some("bash"));
+    assert!(triggered);
+    assert_eq!(out, "");
+    let line = &["whatever", "--line", "whatever sub --js", "17"];
+    let (_, out) = try_complete(&env, line, Some("bash"));
+    assert_eq!(out, "--json\n");
+
+    // `json` is for tools other than shells
+    let (_, out) = try_complete(&env, line, Some("json"));
+    assert!(out.starts_with(r#"{"arg":"--js","quote":null,"completions":["#));
+    assert!(out.contains(r#"{"value":"--json","#));
+    let words = &["whatever", "whatever", "sub", "--xx", ""];
+    let (_, out) = try_complete(&env, words, Some("json"));
+    assert!(out.starts_with(r#"{"error":{"kind":"FlagNotFound""#));
+    let args = ["whatever"].into_iter().map(String::from);
+    assert!(
+        env.try_complete(args, Some("json".to_owned()), &mut vec![])
+            .is_err()
+    );
+
+    let env = CompleteEnv::new(&def::ROOT).subcommand("complete");
+    let words = &["whatever", "complete", "fish", "whatever", "sub", "--js"];
+    let (triggered, out) = try_complete(&env, words, None);
+    assert!(triggered);
+    assert!(out.contains("--json\t"));
+
+    let (_, out) = try_complete(&env, &["/path/to/whatever"], Some("bash"));
+    assert!(out.contains("complete -F _supplements_whatever whatever"));
+    assert!(out.contains(" complete bash --line "));
+    let env = CompleteEnv::new(&def::ROOT).names(&["w1", "w2"]).bin("bin");
+    let (_, out) = try_complete(&env, &["/path/to/whatever"], Some("zsh"));
+    assert!(out.starts_with("#compdef w1 w2\n"));
+    assert!(out.contains("COMPLETE=zsh bin --line "));
+}
+
+#[test]
+fn test_completer_error() {
+    const FAILING_ARG: Arg = Arg {
+        id: id::Valued::Single(id::SingleVal::new(line!(), "")),
+        comp_options: |_, _, _, _| Err("git is not found".into()),
+        max_values: 1,
+        last: false,
+        value_delimiter: None,
+        required: false,
+    };
+    const CMD: Command = Command {
+        id: id::NoVal::new(line!(), ""),
+        name: "failing",
+        aliases: &[],
+        hidden_aliases: &[],
+        description: "",
+        all_flags: &[],
+        args: &[FAILING_ARG],
+        commands: &[],
+        hidden: false,
+    };
+    let args = ["failing", "x"].into_iter().map(String::from);
+    let err = CMD.supplement(args).unwrap_err();
+    assert_eq!(err, Error::Completer("git is not found".to_owned()));
+
+    let env = CompleteEnv::new(&CMD);
+    for (shell, expected) in [
+        ("zsh", "MESSAGE\tgit is not found\nEND\n"),
+        ("fish", "xERR\tgit is not found\nx_\n"),
+        ("bash", ""),
+    ] {
+        let mut w = vec![];
+        let args = ["failing", "--line", "failing x", "9"].into_iter();
+        let triggered = env
+            .try_complete(args.map(String::from), Some(shell.to_owned()), &mut w)
+            .unwrap();
+        assert!(triggered);
+        assert_eq!(String::from_utf8(w).unwrap(), expected);
+    }
+}
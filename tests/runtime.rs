@@ -0,0 +1,233 @@
+#![cfg(feature = "clap-4")]
+
+use clap4::builder::PossibleValue;
+use clap4::{Arg, ArgAction, Command, ValueHint};
+use std::time::Duration;
+use supplements::error::Error;
+use supplements::history::HistoryUnit;
+use supplements::{Completion, Fallback, Guard, History, runtime};
+
+fn clap_cmd() -> Command {
+    Command::new("git")
+        .arg(Arg::new("git-dir").long("git-dir").global(true))
+        .subcommand(
+            Command::new("log")
+                .visible_alias("lg")
+                .arg(Arg::new("graph").long("graph").action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("color")
+                        .long("color")
+                        .value_parser(["always", "never"])
+                        .conflicts_with("graph"),
+                )
+                .arg(Arg::new("commit")),
+        )
+        .subcommand(Command::new("checkout").arg(Arg::new("file").num_args(1..)))
+}
+
+fn run(cmd: &runtime::Command, args: &str) -> Vec<String> {
+    let args = args.split(' ').map(String::from);
+    let comps = cmd.supplement(args).unwrap().into_inner().0;
+    let mut ret: Vec<_> = comps.into_iter().map(|c| c.value).collect();
+    ret.sort();
+    ret
+}
+
+#[test]
+fn test_from_clap() {
+    let cmd = runtime::Command::from_clap(&mut clap_cmd());
+    assert_eq!(run(&cmd, "git "), vec!["checkout", "lg", "log"]);
+    assert_eq!(
+        run(&cmd, "git lg -"),
+        vec!["--color", "--git-dir", "--graph"]
+    );
+    assert_eq!(run(&cmd, "git log --graph -"), vec!["--git-dir"]);
+    assert_eq!(run(&cmd, "git log --color "), vec!["always", "never"]);
+    assert_eq!(
+        run(&cmd, "git log --color=n"),
+        vec!["--color=always", "--color=never"]
+    );
+    // Global flags are shared with subcommands
+    assert_eq!(
+        run(&cmd, "git --git-dir x log --color "),
+        vec!["always", "never"]
+    );
+    assert_eq!(run(&cmd, "git checkout -"), vec!["--git-dir"]);
+}
+
+#[test]
+fn test_comp_options() {
+    let cmd = runtime::Command::from_clap(&mut clap_cmd());
+    let git_dir = cmd.find_id(&["git-dir"]).unwrap();
+    assert_eq!(cmd.find_id(&["log", "git-dir"]), Some(git_dir));
+    assert_eq!(cmd.find_id(&["log", "nothing"]), None);
+
+    let cmd = cmd
+        .comp_options(&["log", "commit"], move |_, history: &History, _, _| {
+            let dir = match history.find(git_dir) {
+                Some(HistoryUnit::Single(h)) => h.value.as_str(),
+                _ => "-",
+            };
+            Ok(vec![Completion::new(&format!("HEAD@{dir}"), "")])
+        })
+        .unwrap()
+        .comp_options(&["git-dir"], |_, _, _, _| {
+            Ok(vec![Completion::new(".git", "")])
+        })
+        .unwrap();
+
+    assert_eq!(run(&cmd, "git log "), vec!["HEAD@-"]);
+    assert_eq!(run(&cmd, "git --git-dir my-dir log "), vec!["HEAD@my-dir"]);
+    assert_eq!(run(&cmd, "git --git-dir "), vec![".git"]);
+    assert_eq!(run(&cmd, "git checkout --git-dir "), vec![".git"]);
+}
+
+#[test]
+fn test_comp_options_not_found() {
+    let cmd: runtime::Command = runtime::Command::from_clap(&mut clap_cmd());
+    let res = cmd.comp_options(&["log", "graph"], |_, _, _, _| Ok(vec![]));
+    let err = res.err().unwrap();
+    assert_eq!(err, Error::PathNotFound("log graph".to_owned()));
+    let cmd: runtime::Command = runtime::Command::from_clap(&mut clap_cmd());
+    let res = cmd.comp_options(&["lg", "commit"], |_, _, _, _| Ok(vec![]));
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_ctx() {
+    let cmd = runtime::Command::<Vec<&str>>::from_clap(&mut clap_cmd())
+        .comp_options(&["log", "commit"], |branches: &Vec<&str>, _, _, _| {
+            Ok(branches.iter().map(|b| Completion::new(b, "")).collect())
+        })
+        .unwrap();
+    let branches = vec!["main", "dev"];
+    let args = ["git", "log", ""].into_iter().map(String::from);
+    let comps = cmd
+        .supplement_with_ctx(&branches, &mut History::default(), args)
+        .unwrap()
+        .into_inner()
+        .0;
+    let comps: Vec<_> = comps.into_iter().map(|c| c.value).collect();
+    assert_eq!(comps, vec!["main", "dev"]);
+}
+
+#[test]
+fn test_guard() {
+    let mut clap_cmd = Command::new("git")
+        .arg(Arg::new("path"))
+        .subcommand(Command::new("log"));
+    let cmd: runtime::Command = runtime::Command::from_clap(&mut clap_cmd)
+        .comp_options(&["path"], |_, _, arg, _| match arg {
+            "panic" => panic!("completer panics"),
+            "slow" => {
+                std::thread::sleep(Duration::from_secs(5));
+                Ok(vec![])
+            }
+            _ => Ok(vec![Completion::new("path!", "")]),
+        })
+        .unwrap();
+    let run = |guard: &Guard, args: &str| {
+        let args = args.split(' ').map(String::from);
+        let grp = cmd.supplement_with_guard(guard, &(), &mut History::default(), args);
+        let mut ret: Vec<_> = grp.unwrap().into_inner().0;
+        ret.sort_by(|a, b| a.value.cmp(&b.value));
+        ret.into_iter().map(|c| c.value).collect::<Vec<_>>()
+    };
+
+    // Other things like subcommands are still completed
+    let guard = Guard::default();
+    assert_eq!(run(&guard, "git panic"), vec!["log"]);
+    let guard = Guard::default().timeout(Duration::from_millis(50));
+    assert_eq!(run(&guard, "git slow"), vec!["log"]);
+    assert_eq!(run(&guard, "git panic"), vec!["log"]);
+    assert_eq!(run(&guard, "git x"), vec!["log", "path!"]);
+
+    let guard = Guard::default().fallback(Fallback::Cached);
+    assert_eq!(run(&guard, "git panic"), vec!["log"]);
+    assert_eq!(run(&guard, "git x"), vec!["log", "path!"]);
+    assert_eq!(run(&guard, "git panic"), vec!["log", "path!"]);
+
+    let guard = Guard::default().fallback(Fallback::Files);
+    assert!(run(&guard, "git panic").contains(&"Cargo.toml".to_owned()));
+}
+
+#[test]
+fn test_value_hint() {
+    let mut cmd = Command::new("ssh")
+        .arg(Arg::new("dir").long("dir").value_hint(ValueHint::DirPath))
+        .arg(Arg::new("token").long("token").value_hint(ValueHint::Other))
+        .arg(
+            Arg::new("exec")
+                .long("exec")
+                .value_hint(ValueHint::ExecutablePath),
+        )
+        .arg(Arg::new("file"));
+    let cmd = runtime::Command::from_clap(&mut cmd);
+    assert_eq!(run(&cmd, "ssh --token "), Vec::<String>::new());
+    let dirs = run(&cmd, "ssh --dir ");
+    assert!(dirs.contains(&"src/".to_owned()));
+    assert!(dirs.iter().all(|d| d.ends_with('/')));
+    assert!(run(&cmd, "ssh ").contains(&"Cargo.toml".to_owned()));
+    let execs = run(&cmd, "ssh --exec ");
+    assert!(execs.contains(&"src/".to_owned()));
+    assert!(!execs.contains(&"Cargo.toml".to_owned()));
+    #[cfg(unix)]
+    assert!(run(&cmd, "ssh --exec /bin/s").contains(&"/bin/sh".to_owned()));
+}
+
+#[test]
+fn test_arg_possible_values() {
+    let mut cmd = Command::new("git").arg(Arg::new("mode").value_parser([
+        PossibleValue::new("keep").help("keep changes").alias("k"),
+        PossibleValue::new("force"),
+        PossibleValue::new("legacy").hide(true),
+    ]));
+    let cmd = runtime::Command::from_clap(&mut cmd);
+    assert_eq!(run(&cmd, "git "), vec!["force", "keep"]);
+    let comps = cmd.supplement(["git", ""].map(String::from).into_iter());
+    let comps = comps.unwrap().into_inner().0;
+    assert!(comps.iter().any(|c| c.description == "keep changes"));
+}
+
+#[test]
+fn test_delimited_arg() {
+    let mut cmd = Command::new("cargo")
+        .arg(
+            Arg::new("features")
+                .value_delimiter(',')
+                .value_parser(["a", "b", "c"]),
+        )
+        .arg(Arg::new("target").value_parser(["x86"]));
+    let cmd = runtime::Command::from_clap(&mut cmd);
+    assert_eq!(run(&cmd, "cargo a,"), vec!["a,b", "a,c"]);
+
+    let features = cmd.find_id(&["features"]).unwrap();
+    let mut history = History::default();
+    let args = ["cargo", "a,b", ""].into_iter().map(String::from);
+    let comps = cmd.supplement_with_history(&mut history, args).unwrap();
+    assert_eq!(comps.into_inner().0, vec![Completion::new("x86", "")]);
+    let values = match history.find(features) {
+        Some(HistoryUnit::Multi(h)) => h.values.clone(),
+        h => panic!("unexpected history {h:?}"),
+    };
+    assert_eq!(values, vec!["a", "b"]);
+}
+
+#[test]
+fn test_requires() {
+    let mut clap_cmd = clap_cmd().mut_subcommand("log", |log| {
+        log.arg(Arg::new("stat").long("stat").action(ArgAction::SetTrue))
+    });
+    let cmd: runtime::Command = runtime::Command::from_clap(&mut clap_cmd)
+        .requires(&["log", "stat"], &["graph"])
+        .unwrap();
+    assert!(!run(&cmd, "git log -").contains(&"--stat".to_owned()));
+    assert!(run(&cmd, "git log --graph -").contains(&"--stat".to_owned()));
+
+    let cmd: runtime::Command = runtime::Command::from_clap(&mut clap_cmd);
+    let res = cmd.requires(&["log", "stat"], &["nothing"]);
+    assert_eq!(
+        res.err(),
+        Some(Error::PathNotFound("log nothing".to_owned()))
+    );
+}